@@ -1,8 +1,55 @@
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Error, Formatter};
 
-/// The AST node for expressions.
+/// A byte-offset range into the original source text that produced a
+/// [`Stmt`]/[`Expr`] node, so a [`crate::compiler::CompileError`] can point
+/// back at exactly the source that caused it.
+///
+/// The grammar that would stamp real positions onto parsed nodes isn't
+/// present in this tree (see `parser::tests` — there's no `.lalrpop` source
+/// to extend), so every node built today carries `Span::default()`. The
+/// type exists so the compiler can already thread spans through
+/// consistently; wiring up real positions is grammar work for later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+/// The AST node for expressions: an [`ExprKind`] plus the [`Span`] of
+/// source it came from.
 #[derive(Clone, PartialEq)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Builds an `Expr` with no known span. Used everywhere in this tree
+    /// today, since nothing stamps real positions yet (see [`Span`]).
+    pub fn spanned(kind: ExprKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+impl Debug for Expr {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        Debug::fmt(&self.kind, fmt)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum ExprKind {
     Boolean(bool),
     Integer(i64),
     Float(f64),
@@ -14,18 +61,33 @@ pub enum Expr {
     Time(String),
     Path(String),
     As(Box<Expr>, String, Box<Expr>),
+    /// `{ stmt1; stmt2; ...; expr }` — a block used in expression position:
+    /// its leading statements run for effect/bindings in a nested scope,
+    /// and the final statement (which must be a [`StmtKind::Expr`]) is the
+    /// block's value. See `compiler::Interpreter::interpret_expr`'s arm for
+    /// how this differs from [`StmtKind::Block`], which always discards its
+    /// value.
+    Block(Vec<Stmt>),
     Index(Box<Expr>, String),
+    /// `count(<path>, 5m)` — the number of messages that have arrived on
+    /// `path` within the trailing `5m` window, re-evaluated as the window
+    /// slides. Only meaningful as (part of) a `when` guard; there is no
+    /// general way to evaluate it as a one-off expression.
+    Count(Box<Expr>, Box<Expr>),
+    /// `name(arg1, arg2, ...)` — invokes the function `name` declared by a
+    /// [`StmtKind::Func`], passing `args` as its parameters.
+    Call(String, Vec<Expr>),
 }
-impl Debug for Expr {
+impl Debug for ExprKind {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match self {
-            Expr::Boolean(b) => write!(fmt, "{b:?}"),
-            Expr::Integer(i) => write!(fmt, "{i:?}"),
-            Expr::Float(f) => write!(fmt, "{f:?}"),
-            Expr::Binary(l, op, r) => write!(fmt, "({l:?} {op:?} {r:?})"),
-            Expr::Ident(i) => write!(fmt, "{i}"),
-            Expr::String(s) => write!(fmt, "{s:?}"),
-            Expr::Object(props) => {
+            ExprKind::Boolean(b) => write!(fmt, "{b:?}"),
+            ExprKind::Integer(i) => write!(fmt, "{i:?}"),
+            ExprKind::Float(f) => write!(fmt, "{f:?}"),
+            ExprKind::Binary(l, op, r) => write!(fmt, "({l:?} {op:?} {r:?})"),
+            ExprKind::Ident(i) => write!(fmt, "{i}"),
+            ExprKind::String(s) => write!(fmt, "{s:?}"),
+            ExprKind::Object(props) => {
                 write!(fmt, "{{")?;
                 for (i, (k, v)) in props.iter().enumerate() {
                     if i > 0 {
@@ -35,11 +97,32 @@ impl Debug for Expr {
                 }
                 write!(fmt, "}}")
             }
-            Expr::Duration(d) => write!(fmt, "{d}"),
-            Expr::Time(t) => write!(fmt, "{t}"),
-            Expr::Path(p) => write!(fmt, "<{p}>"),
-            Expr::As(init, name, cont) => write!(fmt, "{init:?} as {name} in {cont:?}",),
-            Expr::Index(obj, prop) => write!(fmt, "{obj:?}.{prop}",),
+            ExprKind::Duration(d) => write!(fmt, "{d}"),
+            ExprKind::Time(t) => write!(fmt, "{t}"),
+            ExprKind::Path(p) => write!(fmt, "<{p}>"),
+            ExprKind::As(init, name, cont) => write!(fmt, "{init:?} as {name} in {cont:?}",),
+            ExprKind::Block(stmts) => {
+                write!(fmt, "{{")?;
+                for (i, s) in stmts.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, " ")?;
+                    }
+                    write!(fmt, "{:?};", s)?;
+                }
+                write!(fmt, "}}")
+            }
+            ExprKind::Index(obj, prop) => write!(fmt, "{obj:?}.{prop}",),
+            ExprKind::Count(path, window) => write!(fmt, "count({path:?}, {window:?})"),
+            ExprKind::Call(name, args) => {
+                write!(fmt, "{name}(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, ", ")?;
+                    }
+                    write!(fmt, "{arg:?}")?;
+                }
+                write!(fmt, ")")
+            }
         }
     }
 }
@@ -48,9 +131,17 @@ impl Debug for Expr {
 pub enum BinaryOpcode {
     Mul,
     Div,
+    Mod,
     Add,
     Sub,
     Eql,
+    Ne,
+    Lt,
+    Gt,
+    Lte,
+    Gte,
+    And,
+    Or,
 }
 
 impl Debug for BinaryOpcode {
@@ -58,15 +149,50 @@ impl Debug for BinaryOpcode {
         match self {
             BinaryOpcode::Mul => write!(fmt, "*"),
             BinaryOpcode::Div => write!(fmt, "/"),
+            BinaryOpcode::Mod => write!(fmt, "%"),
             BinaryOpcode::Add => write!(fmt, "+"),
             BinaryOpcode::Sub => write!(fmt, "-"),
             BinaryOpcode::Eql => write!(fmt, "is"),
+            BinaryOpcode::Ne => write!(fmt, "!="),
+            BinaryOpcode::Lt => write!(fmt, "<"),
+            BinaryOpcode::Gt => write!(fmt, ">"),
+            BinaryOpcode::Lte => write!(fmt, "<="),
+            BinaryOpcode::Gte => write!(fmt, ">="),
+            BinaryOpcode::And => write!(fmt, "&&"),
+            BinaryOpcode::Or => write!(fmt, "||"),
         }
     }
 }
 
-#[derive(PartialEq)]
-pub enum Stmt {
+/// The AST node for statements: a [`StmtKind`] plus the [`Span`] of source
+/// it came from. See [`Expr`]'s doc comment for why every node in this tree
+/// carries `Span::default()` today.
+#[derive(Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Builds a `Stmt` with no known span. Used everywhere in this tree
+    /// today, since nothing stamps real positions yet (see [`Span`]).
+    pub fn spanned(kind: StmtKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+impl Debug for Stmt {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        Debug::fmt(&self.kind, fmt)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum StmtKind {
     Block(Vec<Stmt>),
     Set(String, Expr),
     Let(String, Expr),
@@ -74,18 +200,41 @@ pub enum Stmt {
     //Once(String, Expr, Box<Stmt>),
     Wait(Expr, Box<Stmt>),
     At(Expr, Box<Stmt>),
+    /// `every <duration> <stmt>` — re-arms itself after each run, so `stmt`
+    /// fires on a recurring interval without a `when`/`wait` chain having to
+    /// re-trigger it by hand. Built on the same spawned-thread model as
+    /// [`StmtKind::Wait`]; see `compiler::Interpreter::interpret_stmt`.
+    Every(Expr, Box<Stmt>),
     Expr(Expr),
     Print(Expr),
     Scene(String, Box<Stmt>),
     Start(String),
     Stop(String),
-    //Func(String, Vec<String>, Box<Stmt>),
+    /// `try { ... } on error <name> { ... }` — if a statement inside the
+    /// guarded block fails, `name` is bound to the fault kind it raised for
+    /// the duration of the handler block.
+    Try(Box<Stmt>, String, Box<Stmt>),
+    /// `sequence name { at +0s set <a> 1; at +2s set <b> 0; }` — a
+    /// precompiled, drift-free timed playback: each `(offset, action)` pair
+    /// fires `offset` after the sequence starts, timed off one shared anchor
+    /// instead of a chain of `wait`s whose latency would otherwise
+    /// compound. Like `scene`, implicitly defines a start/stop pair.
+    Sequence(String, Vec<(Expr, Stmt)>),
+    /// `func name(p1, p2) { ...; return <expr>; }` — a callable procedure,
+    /// invoked through [`ExprKind::Call`]. Unlike `scene`, its body does not
+    /// close over the enclosing scope: its parameters are its only
+    /// bindings.
+    Func(String, Vec<String>, Box<Stmt>),
+    /// `return <expr>;` — ends the innermost enclosing `func` body, handing
+    /// `expr`'s value back to the call site. Only meaningful inside a
+    /// `func` body; see [`StmtKind::Func`].
+    Return(Expr),
 }
 
-impl Debug for Stmt {
+impl Debug for StmtKind {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match self {
-            Stmt::Block(stmts) => {
+            StmtKind::Block(stmts) => {
                 write!(fmt, "[")?;
                 for (i, s) in stmts.iter().enumerate() {
                     if i > 0 {
@@ -95,16 +244,34 @@ impl Debug for Stmt {
                 }
                 write!(fmt, "]")
             }
-            Stmt::Set(path, expr) => write!(fmt, "set {} {:?}", path, expr),
-            Stmt::Expr(expr) => write!(fmt, "{:?}", expr),
-            Stmt::Let(id, expr) => write!(fmt, "let {} = {:?}", id, expr),
-            Stmt::When(expr, body) => write!(fmt, "when {:?} {:?}", expr, body),
-            Stmt::Wait(expr, body) => write!(fmt, "wait {:?} {:?}", expr, body),
-            Stmt::At(expr, body) => write!(fmt, "at {:?} {:?}", expr, body),
-            Stmt::Print(expr) => write!(fmt, "print {:?}", expr),
-            Stmt::Scene(id, body) => write!(fmt, "scene {} {:?}", id, body),
-            Stmt::Start(id) => write!(fmt, "start {}", id),
-            Stmt::Stop(id) => write!(fmt, "stop {}", id),
+            StmtKind::Set(path, expr) => write!(fmt, "set {} {:?}", path, expr),
+            StmtKind::Expr(expr) => write!(fmt, "{:?}", expr),
+            StmtKind::Let(id, expr) => write!(fmt, "let {} = {:?}", id, expr),
+            StmtKind::When(expr, body) => write!(fmt, "when {:?} {:?}", expr, body),
+            StmtKind::Wait(expr, body) => write!(fmt, "wait {:?} {:?}", expr, body),
+            StmtKind::At(expr, body) => write!(fmt, "at {:?} {:?}", expr, body),
+            StmtKind::Every(expr, body) => write!(fmt, "every {:?} {:?}", expr, body),
+            StmtKind::Print(expr) => write!(fmt, "print {:?}", expr),
+            StmtKind::Scene(id, body) => write!(fmt, "scene {} {:?}", id, body),
+            StmtKind::Start(id) => write!(fmt, "start {}", id),
+            StmtKind::Stop(id) => write!(fmt, "stop {}", id),
+            StmtKind::Try(body, name, handler) => {
+                write!(fmt, "try {:?} on error {} {:?}", body, name, handler)
+            }
+            StmtKind::Sequence(id, steps) => {
+                write!(fmt, "sequence {} [", id)?;
+                for (i, (offset, action)) in steps.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, " ")?;
+                    }
+                    write!(fmt, "at {:?} {:?};", offset, action)?;
+                }
+                write!(fmt, "]")
+            }
+            StmtKind::Func(name, params, body) => {
+                write!(fmt, "func {}({}) {:?}", name, params.join(", "), body)
+            }
+            StmtKind::Return(expr) => write!(fmt, "return {:?}", expr),
         }
     }
 }
@@ -1,10 +1,23 @@
 use anyhow::anyhow;
 use env_logger;
-use jim::{compiler::Interpreter, mqtt_engine::MQTTEngine, vm::VM, Compile, Result};
+use jim::{
+    ast,
+    compiler::{Code, CompileError, Interpreter},
+    config::{self, Config},
+    mqtt_engine::{MQTTEngine, RetryPolicy},
+    parser,
+    vm::VM,
+    Compile, Result,
+};
+use std::collections::HashMap;
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::{fs, sync::Arc};
 use structopt::StructOpt;
-use tokio::{select, signal, sync::broadcast, task::JoinSet};
+use tokio::{
+    select, signal,
+    sync::{broadcast, mpsc},
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "example", about = "An example of StructOpt usage.")]
@@ -22,9 +35,205 @@ struct Opt {
         env = "JIM_DIR"
     )]
     dir: PathBuf,
+
+    /// Latitude of this installation, in degrees, used to resolve solar
+    /// `at` events (`#sunrise`, `#dusk`, etc.)
+    #[structopt(long, default_value = "0.0", env = "JIM_LAT")]
+    lat: f64,
+
+    /// Longitude of this installation, in degrees, used to resolve solar
+    /// `at` events (`#sunrise`, `#dusk`, etc.)
+    #[structopt(long, default_value = "0.0", env = "JIM_LON")]
+    lon: f64,
+
+    /// IANA timezone of this installation (e.g. `America/New_York`), used
+    /// alongside `lat`/`lon` to resolve solar `at` events and wall-clock
+    /// literals (`10:05PM`).
+    #[structopt(long, default_value = "UTC", env = "JIM_TZ")]
+    timezone: String,
+
+    /// Path to a TOML config file (see [`jim::config::Config`]). When given,
+    /// its `mqtt_url`/`lat`/`lon`/`timezone`/`script_dir` take over from
+    /// the flags above, and both it and `script_dir` are watched so edits
+    /// take effect without restarting `jim`: a changed `.jim` script
+    /// restarts just that file's scenes, and a changed config file
+    /// restarts everything.
+    #[structopt(long, parse(from_os_str), env = "JIM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Precompile a script or run an already-compiled one, instead of
+    /// watching `dir` for `.jim` files.
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// See [`jim::compiler::Code::write_to`]/[`jim::compiler::Code::read_from`]:
+/// a script can be compiled once and the bytecode shipped to a device that
+/// shouldn't need to carry the parser.
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Compile a `.jim` source file to a loadable bytecode file.
+    Compile {
+        /// `.jim` source file to compile
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Where to write the compiled bytecode
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Run a file previously produced by `compile`, skipping the parser.
+    Run {
+        /// Compiled bytecode file to run
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Start an interactive, multi-line session against the MQTT broker.
+    Repl,
+}
+
+impl Opt {
+    /// Parses `timezone` as an IANA timezone name (e.g. `America/New_York`),
+    /// the form [`chrono_tz::Tz`]'s `FromStr` impl expects.
+    fn tz(&self) -> Result<chrono_tz::Tz> {
+        self.timezone
+            .parse()
+            .map_err(|_| anyhow!("'{}' is not a recognized IANA timezone", self.timezone))
+    }
+}
+
+const JIM_EXT: &str = config::SCRIPT_EXT;
+
+/// A single running `.jim` file: its own shutdown channel so it can be
+/// stopped and reloaded independently of every other running file, and the
+/// handle its `VM::run` future finishes on.
+struct ScriptTask {
+    shutdown_tx: broadcast::Sender<()>,
+    handle: tokio::task::JoinHandle<Result<()>>,
 }
 
-const JIM_EXT: &str = "jim";
+impl ScriptTask {
+    /// Compiles `path` and spawns it onto a fresh `VM`. `done` is notified
+    /// with `path` once the task finishes, whether normally, on error, or
+    /// because [`Self::stop`] asked it to.
+    fn spawn(
+        path: PathBuf,
+        mqtt: Arc<MQTTEngine>,
+        done: mpsc::UnboundedSender<PathBuf>,
+    ) -> Result<Self> {
+        let code = compile_script(&path)?;
+        Ok(Self::spawn_code(path, code, mqtt, done))
+    }
+
+    /// Spawns already-compiled `code` onto a fresh `VM`, same as [`Self::spawn`]
+    /// but skipping the read-and-compile step — used by [`reload_script`],
+    /// which needs the new file to compile successfully *before* tearing
+    /// down the old task, so a broken edit doesn't kill a working one.
+    fn spawn_code(
+        path: PathBuf,
+        code: Code,
+        mqtt: Arc<MQTTEngine>,
+        done: mpsc::UnboundedSender<PathBuf>,
+    ) -> Self {
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let task_path = path.clone();
+        let handle = tokio::spawn(async move {
+            log::debug!("running file: {}", task_path.display());
+            let vm = VM::new(mqtt);
+            let result = vm.run(code, shutdown_rx).await;
+            if let Err(err) = &result {
+                log::error!("{}: {}", task_path.display(), err);
+            }
+            log::debug!("finished file: {}", task_path.display());
+            let _ = done.send(task_path);
+            result
+        });
+        Self {
+            shutdown_tx,
+            handle,
+        }
+    }
+
+    /// Signals this file's `VM` to stop and waits for it to actually finish.
+    async fn stop(self) -> Result<()> {
+        let _ = self.shutdown_tx.send(());
+        self.handle.await?
+    }
+}
+
+/// Reads and compiles `path`, rendering a caret-annotated snippet instead of
+/// just propagating the bare message if this was a `CompileError` (as
+/// opposed to, say, a parse error) so the offending line/column is obvious
+/// in the log.
+fn compile_script(path: &std::path::Path) -> Result<Code> {
+    let source = fs::read_to_string(path)?;
+    Interpreter::from_source(&source).map_err(|err| {
+        if let Some(err) = err.downcast_ref::<CompileError>() {
+            log::error!("{}: {}", path.display(), err.render(&source));
+        }
+        err
+    })
+}
+
+/// Scans `script_dir` for `.jim` files and spawns each one, logging (rather
+/// than failing outright on) any single file that doesn't compile, so one
+/// broken script doesn't keep the rest from running.
+fn spawn_scripts(
+    script_dir: &std::path::Path,
+    mqtt: &Arc<MQTTEngine>,
+    done: &mpsc::UnboundedSender<PathBuf>,
+) -> Result<HashMap<PathBuf, ScriptTask>> {
+    let mut tasks = HashMap::new();
+    for entry in fs::read_dir(script_dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == JIM_EXT) {
+            match ScriptTask::spawn(path.clone(), mqtt.clone(), done.clone()) {
+                Ok(task) => {
+                    tasks.insert(path, task);
+                }
+                Err(err) => log::error!("{}: {}", path.display(), err),
+            }
+        }
+    }
+    Ok(tasks)
+}
+
+/// Stops and restarts `path`'s entry in `tasks`, leaving every other running
+/// file untouched. A deleted file is stopped and simply not respawned.
+///
+/// Compiles the new version *before* touching the old task: if it fails to
+/// compile, the diagnostics are logged and whatever was already running for
+/// `path` is left in place rather than torn down, so a typo while iterating
+/// on a script doesn't kill the scenes that file was already running.
+async fn reload_script(
+    tasks: &mut HashMap<PathBuf, ScriptTask>,
+    path: PathBuf,
+    mqtt: &Arc<MQTTEngine>,
+    done: &mpsc::UnboundedSender<PathBuf>,
+) -> Result<()> {
+    if !path.is_file() {
+        if let Some(old) = tasks.remove(&path) {
+            old.stop().await?;
+        }
+        return Ok(());
+    }
+    let code = match compile_script(&path) {
+        Ok(code) => code,
+        Err(err) => {
+            log::error!(
+                "{}: {err}, keeping the previously running version",
+                path.display()
+            );
+            return Ok(());
+        }
+    };
+    log::info!("reloading {}", path.display());
+    if let Some(old) = tasks.remove(&path) {
+        old.stop().await?;
+    }
+    let task = ScriptTask::spawn_code(path.clone(), code, mqtt.clone(), done.clone());
+    tasks.insert(path, task);
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -33,57 +242,101 @@ async fn main() -> Result<()> {
     let opt = Opt::from_args();
     log::debug!("options {:?}", opt);
 
-    let mqtt = MQTTEngine::new(&opt.mqtt_url)?;
-    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    match opt.command {
+        Some(Command::Compile { input, output }) => return compile(&input, &output),
+        Some(Command::Run { file }) => return run_compiled(&file, &opt).await,
+        Some(Command::Repl) => return repl(&opt).await,
+        None => {}
+    }
 
-    let mut join_set = JoinSet::new();
-
-    for entry in fs::read_dir(opt.dir)? {
-        let entry = entry?;
-        if entry.path().is_file() {
-            if let Some(ext) = entry.path().extension() {
-                if ext == JIM_EXT {
-                    let source = fs::read_to_string(entry.path())?;
-                    let mqtt = mqtt.clone();
-                    let shutdown_rx = shutdown_rx.resubscribe();
-                    let path = entry.path().clone();
-                    join_set.spawn(async move {
-                        log::debug!("running file: {}", path.display());
-                        let code = Interpreter::from_source(&source)?;
-                        let vm = VM::new(mqtt);
-                        vm.run(code, shutdown_rx).await?;
-                        log::debug!("finished file: {} ", path.display());
-                        Ok(()) as Result<()>
-                    });
-                }
-            }
-        }
+    let config = match &opt.config {
+        Some(path) => Some(Config::from_file(path).await?),
+        None => None,
+    };
+
+    let mut mqtt = match &config {
+        Some(config) => MQTTEngine::new(
+            config.mqtt_url.as_deref().unwrap_or(&opt.mqtt_url),
+            (config.lat, config.lon),
+            config.tz()?,
+            None,
+            RetryPolicy::default(),
+        )?,
+        None => MQTTEngine::new(
+            &opt.mqtt_url,
+            (opt.lat, opt.lon),
+            opt.tz()?,
+            None,
+            RetryPolicy::default(),
+        )?,
+    };
+    let mut script_dir = config
+        .as_ref()
+        .map_or_else(|| opt.dir.clone(), |config| config.script_dir.clone());
+
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let mut tasks = spawn_scripts(&script_dir, &mqtt, &done_tx)?;
+
+    if tasks.is_empty() && opt.config.is_none() {
+        return if let Ok(mqtt) = Arc::try_unwrap(mqtt) {
+            mqtt.shutdown().await
+        } else {
+            Err(anyhow!("not all threads stopped"))
+        };
+    }
+
+    let (changes_tx, mut changes_rx) = mpsc::unbounded_channel();
+    if let Some(config_path) = &opt.config {
+        config::watch(config_path.clone(), script_dir.clone(), changes_tx)?;
     }
 
-    // Wait for user supplied signal or for the program to run to completion.
+    // Wait for user supplied signal, a watched config or script change, or for
+    // every script to run to completion on its own.
     loop {
         select! {
-            // Wait for shutdown signal
             sig = signal::ctrl_c() => {
                 sig?;
-                // Send shutdown to all tasks
-                shutdown_tx.send(())?;
                 break;
             }
-            // Wait for task and error it any task encounters an error
-            res = join_set.join_next() => {
-                if let Some(res) = res {
-                    res??;
-                } else {
-                    // All tasks have finished
+            Some(path) = done_rx.recv() => {
+                tasks.remove(&path);
+                if tasks.is_empty() && opt.config.is_none() {
                     break;
                 }
             }
+            Some(change) = changes_rx.recv() => {
+                match change {
+                    config::Change::Script(path) => {
+                        reload_script(&mut tasks, path, &mqtt, &done_tx).await?;
+                    }
+                    config::Change::Config => {
+                        log::info!("config file changed, reloading everything");
+                        for (_, task) in tasks.drain() {
+                            task.stop().await?;
+                        }
+                        let new_config = Config::from_file(opt.config.as_ref().unwrap()).await?;
+                        let new_mqtt = MQTTEngine::new(
+                            new_config.mqtt_url.as_deref().unwrap_or(&opt.mqtt_url),
+                            (new_config.lat, new_config.lon),
+                            new_config.tz()?,
+                            None,
+                            RetryPolicy::default(),
+                        )?;
+                        if let Ok(old_mqtt) = Arc::try_unwrap(mqtt) {
+                            old_mqtt.shutdown().await?;
+                        }
+                        mqtt = new_mqtt;
+                        script_dir = new_config.script_dir.clone();
+                        tasks = spawn_scripts(&script_dir, &mqtt, &done_tx)?;
+                    }
+                }
+            }
         };
     }
-    // Drain all tasks, they should shutdown gracefully at this point
-    while let Some(res) = join_set.join_next().await {
-        res??;
+
+    // Stop every still-running script; they should shut down gracefully.
+    for (_, task) in tasks.drain() {
+        task.stop().await?;
     }
 
     // Cleanup mqtt
@@ -94,3 +347,166 @@ async fn main() -> Result<()> {
         Err(anyhow!("not all threads stopped"))
     }
 }
+
+/// `jim compile <input> <output>`: parses and compiles `input`, then writes
+/// the resulting bytecode to `output` via [`Code::write_to`].
+fn compile(input: &std::path::Path, output: &std::path::Path) -> Result<()> {
+    let source = fs::read_to_string(input)?;
+    let code = Interpreter::from_source(&source).map_err(|err| {
+        if let Some(err) = err.downcast_ref::<CompileError>() {
+            log::error!("{}: {}", input.display(), err.render(&source));
+        }
+        err
+    })?;
+    code.write_to(output)?;
+    log::info!("compiled {} to {}", input.display(), output.display());
+    Ok(())
+}
+
+/// `jim run <file>`: loads bytecode previously written by `jim compile` via
+/// [`Code::read_from`] and runs it directly, without re-parsing.
+async fn run_compiled(file: &std::path::Path, opt: &Opt) -> Result<()> {
+    let code = Code::read_from(file)?;
+    let mqtt = MQTTEngine::new(
+        &opt.mqtt_url,
+        (opt.lat, opt.lon),
+        opt.tz()?,
+        None,
+        RetryPolicy::default(),
+    )?;
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let vm = VM::new(mqtt.clone());
+    select! {
+        sig = signal::ctrl_c() => {
+            sig?;
+            let _ = shutdown_tx.send(());
+        }
+        res = vm.run(code, shutdown_rx) => {
+            res?;
+        }
+    }
+    if let Ok(mqtt) = Arc::try_unwrap(mqtt) {
+        mqtt.shutdown().await?;
+    }
+    Ok(())
+}
+
+/// `jim repl`: reads statements from stdin, buffering across lines until a
+/// fragment is complete (see [`is_balanced`]), then compiles and runs it.
+///
+/// A `CompileError` prints its diagnostic and the session stays alive
+/// instead of crashing. The VM itself has no way to pause a running thread
+/// and resume it later — every `Thread` runs to `Term` inside one
+/// `vm.run()` call — so each fragment still executes through a fresh `VM`.
+/// What the request actually needs to survive between entries, `let`
+/// bindings, is tracked at this level instead: every `let` seen so far is
+/// re-declared ahead of each new fragment before it compiles, so earlier
+/// names stay visible without re-running earlier `print`/`set`/`wait` side
+/// effects.
+async fn repl(opt: &Opt) -> Result<()> {
+    let mqtt = MQTTEngine::new(
+        &opt.mqtt_url,
+        (opt.lat, opt.lon),
+        opt.tz()?,
+        None,
+        RetryPolicy::default(),
+    )?;
+    let mut bindings: Vec<(String, ast::Expr)> = Vec::new();
+    let mut buffer = String::new();
+
+    loop {
+        if buffer.is_empty() {
+            print!("> ");
+        } else {
+            print!(". ");
+        }
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            break; // EOF
+        }
+        buffer.push_str(&line);
+
+        if !is_balanced(&buffer) {
+            continue;
+        }
+        let source = std::mem::take(&mut buffer);
+        if source.trim().is_empty() {
+            continue;
+        }
+
+        let fragment = match parser::parse(&source) {
+            Ok(fragment) => fragment,
+            Err(err) => {
+                println!("error: {err}");
+                continue;
+            }
+        };
+        let stmts = match fragment.kind {
+            ast::StmtKind::Block(stmts) => stmts,
+            kind => vec![ast::Stmt::new(kind, fragment.span)],
+        };
+
+        for stmt in &stmts {
+            if let ast::StmtKind::Let(name, expr) = &stmt.kind {
+                bindings.retain(|(bound, _)| bound != name);
+                bindings.push((name.clone(), expr.clone()));
+            }
+        }
+
+        let mut program: Vec<ast::Stmt> = bindings
+            .iter()
+            .map(|(name, expr)| ast::Stmt::spanned(ast::StmtKind::Let(name.clone(), expr.clone())))
+            .collect();
+        program.extend(stmts);
+
+        let code = match Interpreter::from_ast(ast::Stmt::spanned(ast::StmtKind::Block(program))) {
+            Ok(code) => code,
+            Err(err) => {
+                println!("{}", err.render(&source));
+                continue;
+            }
+        };
+
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let vm = VM::new(mqtt.clone());
+        if let Err(err) = vm.run(code, shutdown_rx).await {
+            println!("error: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// Whether `buffer` looks like a complete fragment worth trying to parse:
+/// braces balanced (ignoring ones inside string literals) and the last
+/// non-whitespace character ends a statement, the same kind of
+/// brace-depth/continuation tracking the schala REPL uses to decide when to
+/// stop buffering and actually evaluate a multi-line entry.
+fn is_balanced(buffer: &str) -> bool {
+    let mut depth: i32 = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in buffer.chars() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    if depth > 0 {
+        return false;
+    }
+    matches!(buffer.trim_end().chars().last(), Some(';') | Some('}'))
+}
@@ -0,0 +1,609 @@
+//! A semantic-analysis pass over [`ast::Stmt`] that runs before compilation,
+//! catching mistakes statically (`wait` on something that isn't a duration,
+//! an identifier used before any enclosing `let`/`func`/`as ... in` bound
+//! it, `set` on a broker-reserved path) instead of surfacing them as a
+//! confusing runtime failure deep in `vm::VM` — or, for anything this pass
+//! can't see, not at all until the day the buggy path actually triggers.
+//! Modeled on the Zinc compiler's semantic element errors (index out of
+//! range, pushing an invalid type): a typed reason plus the source location
+//! that caused it, so callers get a diagnostic they can match on instead of
+//! a bare string.
+//!
+//! Every node's `span` is `Span::default()` until the parser starts
+//! stamping real positions (see [`crate::ast::Span`]); `location` below is
+//! threaded through regardless, so nothing here needs to change the day
+//! that lands.
+
+use crate::ast::{BinaryOpcode, Expr, ExprKind, Span, Stmt, StmtKind};
+use std::collections::HashSet;
+use std::fmt;
+
+/// A human-readable name for the static shape of a literal expression —
+/// what [`check`] reports it actually found where a different shape was
+/// required (see [`SemanticError::WaitRequiresDuration`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeName {
+    Boolean,
+    Integer,
+    Float,
+    String,
+    Duration,
+    Time,
+    Path,
+    Object,
+}
+
+impl fmt::Display for TypeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TypeName::Boolean => "boolean",
+            TypeName::Integer => "integer",
+            TypeName::Float => "float",
+            TypeName::String => "string",
+            TypeName::Duration => "duration",
+            TypeName::Time => "time",
+            TypeName::Path => "path",
+            TypeName::Object => "object",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SemanticError {
+    /// `wait <expr> ...` where `expr` is a literal that is provably not a
+    /// duration (e.g. `wait "5s" ...`, `wait 5 ...`). Only literal `Expr`s
+    /// are classified; anything computed (an `Ident`, `Call`, a watched
+    /// `Path`, ...) can't be judged without running the program, so it's
+    /// let through here and still fails at runtime if it's wrong.
+    WaitRequiresDuration { found: TypeName, location: Span },
+    /// `at <expr> ...` where `expr` is a literal that is provably not a
+    /// time. Mirrors [`SemanticError::WaitRequiresDuration`]; see its doc
+    /// comment for why only literals are classified.
+    AtRequiresTime { found: TypeName, location: Span },
+    /// An `Ident` referenced before any enclosing `let`, `func` parameter,
+    /// or `as ... in` binding declared it.
+    UndefinedIdentifier { name: String, location: Span },
+    /// `set <path> ...` where `path` is a broker-reserved topic (starts
+    /// with `$`, e.g. `$SYS/...`) that no client is allowed to publish to.
+    SetOnNonWritablePath { path: String, location: Span },
+    /// `set <path> <expr>` where `expr` is a literal of a kind `set` can't
+    /// actually hand to an engine (today, only `Boolean`: `TryFrom<Value>
+    /// for Vec<u8>` has no encoding for it and panics via `todo!()` at
+    /// runtime instead of erroring).
+    SetRequiresSettableValue { found: TypeName, location: Span },
+    /// Both sides of an `is`/`!=` comparison are literals whose kinds can
+    /// never compare meaningfully (e.g. a `Duration` against a `Boolean`).
+    /// A literal `Path` is never flagged here: `<path> is ...`/`<path> !=
+    /// ...` is the idiom for watching a path's live value (see
+    /// `compiler::Interpreter::interpret_stmt`'s `When` guard
+    /// handling), so a `Path`'s "kind" isn't knowable until the program
+    /// runs.
+    IncomparableTypes {
+        lhs: TypeName,
+        rhs: TypeName,
+        location: Span,
+    },
+    /// `start <name>`/`stop <name>` where `name` wasn't declared by any
+    /// enclosing `scene`. Reported separately from
+    /// [`SemanticError::UndefinedIdentifier`] since the fix is always "add a
+    /// `scene` declaration", not any of the other binding forms.
+    UndeclaredScene { name: String, location: Span },
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemanticError::WaitRequiresDuration { found, .. } => {
+                write!(f, "`wait` requires a duration, found {found}")
+            }
+            SemanticError::AtRequiresTime { found, .. } => {
+                write!(f, "`at` requires a time, found {found}")
+            }
+            SemanticError::UndefinedIdentifier { name, .. } => {
+                write!(f, "undefined identifier `{name}`")
+            }
+            SemanticError::SetOnNonWritablePath { path, .. } => {
+                write!(f, "`set` on non-writable path `{path}`")
+            }
+            SemanticError::SetRequiresSettableValue { found, .. } => {
+                write!(f, "`set` cannot send a {found} value")
+            }
+            SemanticError::IncomparableTypes { lhs, rhs, .. } => {
+                write!(f, "cannot compare {lhs} to {rhs}")
+            }
+            SemanticError::UndeclaredScene { name, .. } => {
+                write!(f, "`{name}` is not a declared scene")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SemanticError {}
+
+impl SemanticError {
+    /// The span in the original source that caused this error, regardless
+    /// of which variant it is — used by [`crate::diagnostics::render`] to
+    /// point at the offending code.
+    pub fn location(&self) -> Span {
+        match self {
+            SemanticError::WaitRequiresDuration { location, .. }
+            | SemanticError::AtRequiresTime { location, .. }
+            | SemanticError::UndefinedIdentifier { location, .. }
+            | SemanticError::SetOnNonWritablePath { location, .. }
+            | SemanticError::SetRequiresSettableValue { location, .. }
+            | SemanticError::IncomparableTypes { location, .. }
+            | SemanticError::UndeclaredScene { location, .. } => *location,
+        }
+    }
+}
+
+/// Whether `a` and `b` can ever compare meaningfully with `is`/`!=`: the
+/// same kind, or either order of the one cross-numeric pair this language
+/// treats as comparable.
+fn comparable(a: TypeName, b: TypeName) -> bool {
+    a == b
+        || matches!(
+            (a, b),
+            (TypeName::Integer, TypeName::Float) | (TypeName::Float, TypeName::Integer)
+        )
+}
+
+/// Walks `stmt` and returns every [`SemanticError`] it can find, or `Ok(())`
+/// if there are none. Unlike `compiler::CompileError`, this doesn't stop at
+/// the first problem — it reports everything wrong with a program in one
+/// pass, the way a type checker would.
+pub fn check(stmt: &Stmt) -> Result<(), Vec<SemanticError>> {
+    let mut errors = Vec::new();
+    let mut scope: Vec<HashSet<String>> = vec![HashSet::new()];
+    check_stmt(stmt, &mut scope, &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The [`TypeName`] of `expr`, if it's a literal whose shape is knowable
+/// without running the program.
+fn literal_type_name(expr: &Expr) -> Option<TypeName> {
+    match &expr.kind {
+        ExprKind::Boolean(_) => Some(TypeName::Boolean),
+        ExprKind::Integer(_) => Some(TypeName::Integer),
+        ExprKind::Float(_) => Some(TypeName::Float),
+        ExprKind::String(_) => Some(TypeName::String),
+        ExprKind::Duration(_) => Some(TypeName::Duration),
+        ExprKind::Time(_) => Some(TypeName::Time),
+        ExprKind::Path(_) => Some(TypeName::Path),
+        ExprKind::Object(_) => Some(TypeName::Object),
+        _ => None,
+    }
+}
+
+fn declare(scope: &mut [HashSet<String>], name: String) {
+    scope.last_mut().expect("scope never empty").insert(name);
+}
+
+fn is_defined(scope: &[HashSet<String>], name: &str) -> bool {
+    scope.iter().rev().any(|frame| frame.contains(name))
+}
+
+fn check_expr(expr: &Expr, scope: &mut Vec<HashSet<String>>, errors: &mut Vec<SemanticError>) {
+    match &expr.kind {
+        ExprKind::Ident(name) => {
+            if !is_defined(scope, name) {
+                errors.push(SemanticError::UndefinedIdentifier {
+                    name: name.clone(),
+                    location: expr.span,
+                });
+            }
+        }
+        ExprKind::Binary(lhs, op, rhs) => {
+            if matches!(op, BinaryOpcode::Eql | BinaryOpcode::Ne) {
+                if let (Some(lhs_kind), Some(rhs_kind)) =
+                    (literal_type_name(lhs), literal_type_name(rhs))
+                {
+                    // A literal `Path` only names what to watch; its real
+                    // kind isn't knowable until the program runs (see
+                    // `SemanticError::IncomparableTypes`'s doc comment), so
+                    // it's exempt from this check either side.
+                    if lhs_kind != TypeName::Path
+                        && rhs_kind != TypeName::Path
+                        && !comparable(lhs_kind, rhs_kind)
+                    {
+                        errors.push(SemanticError::IncomparableTypes {
+                            lhs: lhs_kind,
+                            rhs: rhs_kind,
+                            location: expr.span,
+                        });
+                    }
+                }
+            }
+            check_expr(lhs, scope, errors);
+            check_expr(rhs, scope, errors);
+        }
+        ExprKind::Object(props) => {
+            for (_, value) in props {
+                check_expr(value, scope, errors);
+            }
+        }
+        ExprKind::As(init, name, cont) => {
+            check_expr(init, scope, errors);
+            scope.push(HashSet::new());
+            declare(scope, name.clone());
+            check_expr(cont, scope, errors);
+            scope.pop();
+        }
+        ExprKind::Block(stmts) => {
+            // Mirrors `StmtKind::Block`'s arm in `check_stmt` below: a new
+            // scope for whatever the block `let`s, regardless of whether
+            // it actually ends in an expression — that structural
+            // requirement is `compiler::CompileError`'s to enforce, not
+            // this pass's (see this module's doc comment).
+            scope.push(HashSet::new());
+            for s in stmts {
+                check_stmt(s, scope, errors);
+            }
+            scope.pop();
+        }
+        ExprKind::Index(obj, _) => check_expr(obj, scope, errors),
+        ExprKind::Count(path, window) => {
+            check_expr(path, scope, errors);
+            check_expr(window, scope, errors);
+        }
+        ExprKind::Call(_, args) => {
+            for arg in args {
+                check_expr(arg, scope, errors);
+            }
+        }
+        ExprKind::Boolean(_)
+        | ExprKind::Integer(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Duration(_)
+        | ExprKind::Time(_)
+        | ExprKind::Path(_) => {}
+    }
+}
+
+fn check_stmt(stmt: &Stmt, scope: &mut Vec<HashSet<String>>, errors: &mut Vec<SemanticError>) {
+    match &stmt.kind {
+        StmtKind::Block(stmts) => {
+            scope.push(HashSet::new());
+            for s in stmts {
+                check_stmt(s, scope, errors);
+            }
+            scope.pop();
+        }
+        StmtKind::Set(path, expr) => {
+            if path.starts_with('$') {
+                errors.push(SemanticError::SetOnNonWritablePath {
+                    path: path.clone(),
+                    location: stmt.span,
+                });
+            }
+            if let Some(found) = literal_type_name(expr) {
+                if found == TypeName::Boolean {
+                    errors.push(SemanticError::SetRequiresSettableValue {
+                        found,
+                        location: expr.span,
+                    });
+                }
+            }
+            check_expr(expr, scope, errors);
+        }
+        StmtKind::Let(name, expr) => {
+            check_expr(expr, scope, errors);
+            declare(scope, name.clone());
+        }
+        StmtKind::When(expr, body) | StmtKind::Every(expr, body) => {
+            check_expr(expr, scope, errors);
+            check_stmt(body, scope, errors);
+        }
+        StmtKind::At(expr, body) => {
+            if let Some(found) = literal_type_name(expr) {
+                if found != TypeName::Time {
+                    errors.push(SemanticError::AtRequiresTime {
+                        found,
+                        location: expr.span,
+                    });
+                }
+            }
+            check_expr(expr, scope, errors);
+            check_stmt(body, scope, errors);
+        }
+        StmtKind::Wait(expr, body) => {
+            if let Some(found) = literal_type_name(expr) {
+                if found != TypeName::Duration {
+                    errors.push(SemanticError::WaitRequiresDuration {
+                        found,
+                        location: expr.span,
+                    });
+                }
+            }
+            check_expr(expr, scope, errors);
+            check_stmt(body, scope, errors);
+        }
+        StmtKind::Expr(expr) | StmtKind::Print(expr) | StmtKind::Return(expr) => {
+            check_expr(expr, scope, errors);
+        }
+        StmtKind::Scene(id, body) => {
+            // Declared the same way `compiler::Interpreter::interpret_stmt`
+            // binds it (before compiling the body, so a scene may refer to
+            // itself), so `start`/`stop` can be checked with the same
+            // scope machinery as any other identifier lookup.
+            declare(scope, id.clone());
+            check_stmt(body, scope, errors);
+        }
+        StmtKind::Start(id) | StmtKind::Stop(id) => {
+            if !is_defined(scope, id) {
+                errors.push(SemanticError::UndeclaredScene {
+                    name: id.clone(),
+                    location: stmt.span,
+                });
+            }
+        }
+        StmtKind::Try(body, name, handler) => {
+            check_stmt(body, scope, errors);
+            scope.push(HashSet::new());
+            declare(scope, name.clone());
+            check_stmt(handler, scope, errors);
+            scope.pop();
+        }
+        StmtKind::Sequence(id, steps) => {
+            declare(scope, id.clone());
+            for (offset, action) in steps {
+                check_expr(offset, scope, errors);
+                check_stmt(action, scope, errors);
+            }
+        }
+        StmtKind::Func(_, params, body) => {
+            // A `func` body sees only its own parameters, not the enclosing
+            // scope, the same way the compiler lowers it.
+            let mut func_scope = vec![params.iter().cloned().collect()];
+            check_stmt(body, &mut func_scope, errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Built directly as AST rather than through `parser::parse`: there's no
+    // `.lalrpop` grammar source in this tree (see `parser::tests`), so
+    // there's no way to confirm e.g. a `$`-prefixed path literal round-trips
+    // through it the way these tests assume.
+    use super::*;
+
+    #[test]
+    fn test_wait_requires_duration() {
+        let ast = Stmt::spanned(StmtKind::Wait(
+            Expr::spanned(ExprKind::String("5s".to_string())),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::Integer(0),
+            )))),
+        ));
+        assert_eq!(
+            Err(vec![SemanticError::WaitRequiresDuration {
+                found: TypeName::String,
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_wait_duration_ok() {
+        let ast = Stmt::spanned(StmtKind::Wait(
+            Expr::spanned(ExprKind::Duration("1s".to_string())),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::Integer(0),
+            )))),
+        ));
+        assert_eq!(Ok(()), check(&ast));
+    }
+
+    #[test]
+    fn test_undefined_identifier() {
+        let ast = Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Ident(
+            "missing".to_string(),
+        ))));
+        assert_eq!(
+            Err(vec![SemanticError::UndefinedIdentifier {
+                name: "missing".to_string(),
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_as_binding_is_defined() {
+        let ast = Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::As(
+            Box::new(Expr::spanned(ExprKind::Integer(1))),
+            "x".to_string(),
+            Box::new(Expr::spanned(ExprKind::Binary(
+                Box::new(Expr::spanned(ExprKind::Ident("x".to_string()))),
+                BinaryOpcode::Add,
+                Box::new(Expr::spanned(ExprKind::Integer(1))),
+            ))),
+        ))));
+        assert_eq!(Ok(()), check(&ast));
+    }
+
+    #[test]
+    fn test_block_expr_scopes_its_bindings() {
+        let ast = Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Block(vec![
+            Stmt::spanned(StmtKind::Let(
+                "x".to_string(),
+                Expr::spanned(ExprKind::Integer(1)),
+            )),
+            Stmt::spanned(StmtKind::Expr(Expr::spanned(ExprKind::Ident(
+                "x".to_string(),
+            )))),
+        ]))));
+        assert_eq!(Ok(()), check(&ast));
+    }
+
+    #[test]
+    fn test_block_expr_binding_does_not_leak() {
+        let ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Expr(Expr::spanned(ExprKind::Block(vec![
+                Stmt::spanned(StmtKind::Let(
+                    "x".to_string(),
+                    Expr::spanned(ExprKind::Integer(1)),
+                )),
+                Stmt::spanned(StmtKind::Expr(Expr::spanned(ExprKind::Ident(
+                    "x".to_string(),
+                )))),
+            ])))),
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Ident(
+                "x".to_string(),
+            )))),
+        ]));
+        assert_eq!(
+            Err(vec![SemanticError::UndefinedIdentifier {
+                name: "x".to_string(),
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_set_on_reserved_path() {
+        let ast = Stmt::spanned(StmtKind::Set(
+            "$SYS/broker/uptime".to_string(),
+            Expr::spanned(ExprKind::String("0".to_string())),
+        ));
+        assert_eq!(
+            Err(vec![SemanticError::SetOnNonWritablePath {
+                path: "$SYS/broker/uptime".to_string(),
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_at_requires_time() {
+        let ast = Stmt::spanned(StmtKind::At(
+            Expr::spanned(ExprKind::String("10:30".to_string())),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::Integer(0),
+            )))),
+        ));
+        assert_eq!(
+            Err(vec![SemanticError::AtRequiresTime {
+                found: TypeName::String,
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_at_time_ok() {
+        let ast = Stmt::spanned(StmtKind::At(
+            Expr::spanned(ExprKind::Time("10:30".to_string())),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::Integer(0),
+            )))),
+        ));
+        assert_eq!(Ok(()), check(&ast));
+    }
+
+    #[test]
+    fn test_set_requires_settable_value() {
+        let ast = Stmt::spanned(StmtKind::Set(
+            "light/kitchen".to_string(),
+            Expr::spanned(ExprKind::Boolean(true)),
+        ));
+        assert_eq!(
+            Err(vec![SemanticError::SetRequiresSettableValue {
+                found: TypeName::Boolean,
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_set_string_ok() {
+        let ast = Stmt::spanned(StmtKind::Set(
+            "light/kitchen".to_string(),
+            Expr::spanned(ExprKind::String("on".to_string())),
+        ));
+        assert_eq!(Ok(()), check(&ast));
+    }
+
+    #[test]
+    fn test_incomparable_types() {
+        let ast = Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Binary(
+            Box::new(Expr::spanned(ExprKind::Duration("5s".to_string()))),
+            BinaryOpcode::Eql,
+            Box::new(Expr::spanned(ExprKind::Boolean(true))),
+        ))));
+        assert_eq!(
+            Err(vec![SemanticError::IncomparableTypes {
+                lhs: TypeName::Duration,
+                rhs: TypeName::Boolean,
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_comparable_numeric_types_ok() {
+        let ast = Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Binary(
+            Box::new(Expr::spanned(ExprKind::Integer(1))),
+            BinaryOpcode::Eql,
+            Box::new(Expr::spanned(ExprKind::Float(1.0))),
+        ))));
+        assert_eq!(Ok(()), check(&ast));
+    }
+
+    #[test]
+    fn test_path_comparison_is_exempt_from_type_check() {
+        // `<path> is "off"` is the idiom for watching a path's live value
+        // (see `compiler::tests::test_when`'s `when` clause); a literal `Path`'s real kind isn't knowable statically,
+        // so it must never be flagged here.
+        let ast = Stmt::spanned(StmtKind::When(
+            Expr::spanned(ExprKind::Binary(
+                Box::new(Expr::spanned(ExprKind::Path("motion".to_string()))),
+                BinaryOpcode::Eql,
+                Box::new(Expr::spanned(ExprKind::String("off".to_string()))),
+            )),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::Integer(0),
+            )))),
+        ));
+        assert_eq!(Ok(()), check(&ast));
+    }
+
+    #[test]
+    fn test_start_requires_declared_scene() {
+        let ast = Stmt::spanned(StmtKind::Start("night".to_string()));
+        assert_eq!(
+            Err(vec![SemanticError::UndeclaredScene {
+                name: "night".to_string(),
+                location: Span::default(),
+            }]),
+            check(&ast)
+        );
+    }
+
+    #[test]
+    fn test_start_declared_scene_ok() {
+        let ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Scene(
+                "night".to_string(),
+                Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                    ExprKind::Integer(0),
+                )))),
+            )),
+            Stmt::spanned(StmtKind::Start("night".to_string())),
+            Stmt::spanned(StmtKind::Stop("night".to_string())),
+        ]));
+        assert_eq!(Ok(()), check(&ast));
+    }
+}
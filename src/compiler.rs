@@ -1,7 +1,7 @@
-use crate::ast::{BinaryOpcode, Expr, Stmt};
+use crate::ast::{BinaryOpcode, Expr, ExprKind, Span, Stmt, StmtKind};
+use crate::fault::Fault;
 use crate::Compile;
-use anyhow::anyhow;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap},
     convert::{TryFrom, TryInto},
@@ -9,18 +9,46 @@ use std::{
     time::Duration,
 };
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
-#[serde(untagged)]
+/// A runtime value, tagged by variant when serialized — unlike the plain
+/// JSON scalars an MQTT payload carries (see [`json_to_value`]/
+/// [`value_to_json`], which convert between the two by hand), this is the
+/// representation [`Code::write_to`]/[`Code::read_from`] persist, so it
+/// needs to round-trip exactly rather than degrade to the nearest JSON
+/// shape.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Value {
     Str(String),
     Path(String),
+    #[serde(with = "duration_millis")]
     Duration(Duration),
     Time(TimeOfDay),
     Float(f64),
     Integer(i64),
     Bool(bool),
     Object(BTreeMap<String, Value>),
+    Array(Vec<Value>),
     Jump(usize),
+    /// The fault kind a failed statement inside a `try` block raised,
+    /// pushed by the VM onto the handler's name when it unwinds there.
+    Fault(Fault),
+}
+
+/// `Value::Duration` holds a `std::time::Duration`, whose own `Serialize`
+/// impl writes a `{secs, nanos}` pair — awkward to read back by hand and
+/// overkill for the whole-millisecond precision `jim` actually uses. This
+/// represents it as a single millisecond count instead.
+mod duration_millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(d.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let millis = u64::deserialize(d)?;
+        Ok(Duration::from_millis(millis))
+    }
 }
 
 impl Display for Value {
@@ -34,6 +62,7 @@ impl Display for Value {
             Value::Integer(i) => write!(f, "{}", i),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Jump(ip) => write!(f, "jmp: {:?}", ip),
+            Value::Fault(fault) => write!(f, "{}", fault),
             Value::Object(props) => {
                 write!(f, "{{")?;
                 for (i, (k, v)) in props.iter().enumerate() {
@@ -44,6 +73,16 @@ impl Display for Value {
                 }
                 write!(f, "}}")
             }
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, v) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
@@ -55,7 +94,7 @@ impl TryFrom<Value> for String {
         match value {
             Value::Str(s) => Ok(s),
             Value::Path(s) => Ok(s),
-            _ => Err(anyhow!("value is not a string")),
+            _ => Err(anyhow::anyhow!("value is not a string")),
         }
     }
 }
@@ -66,15 +105,36 @@ impl TryFrom<Value> for Vec<u8> {
         match value {
             Value::Str(s) => Ok(s.as_bytes().to_vec()),
             Value::Path(s) => Ok(s.as_bytes().to_vec()),
-            Value::Duration(_) => todo!(),
-            Value::Time(_) => todo!(),
+            // Written as whole seconds, the same unit `ExprKind::Duration`
+            // parses from; written back through `Value::try_from(&[u8])`,
+            // this round-trips via `json_to_value`'s integer case rather
+            // than the duration literal's unit-suffixed syntax.
+            Value::Duration(d) => Ok(d.as_secs().to_string().as_bytes().to_vec()),
+            // `Display for TimeOfDay` already gives the forms this needs:
+            // the solar names as-is, and unpadded `H:M` (e.g. `9:5`) for `HM`.
+            Value::Time(t) => Ok(t.to_string().as_bytes().to_vec()),
             Value::Float(f) => Ok(f.to_string().as_bytes().to_vec()),
             Value::Integer(i) => Ok(i.to_string().as_bytes().to_vec()),
-            Value::Bool(_) => todo!(),
+            Value::Bool(b) => Ok(b.to_string().as_bytes().to_vec()),
             Value::Jump(_) => todo!(),
+            // `Display for Fault` already gives its variant name (e.g.
+            // `"PathNotFound"`); round-tripping it back through
+            // `Value::try_from(&[u8])` isn't supported (a `Fault` is never
+            // parsed from a literal or a `get`), but `set`ting one still
+            // needs a byte encoding rather than a panic.
+            Value::Fault(fault) => Ok(fault.to_string().as_bytes().to_vec()),
             Value::Object(props) => {
-                let json = serde_json::to_vec(&props)?;
-                Ok(json)
+                let json = serde_json::Value::Object(
+                    props
+                        .into_iter()
+                        .map(|(k, v)| (k, value_to_json(&v)))
+                        .collect(),
+                );
+                Ok(serde_json::to_vec(&json)?)
+            }
+            Value::Array(items) => {
+                let json = serde_json::Value::Array(items.iter().map(value_to_json).collect());
+                Ok(serde_json::to_vec(&json)?)
             }
         }
     }
@@ -105,7 +165,13 @@ fn json_to_value(v: serde_json::Value) -> Option<Value> {
         }
         serde_json::Value::String(s) => Some(Value::Str(s)),
         serde_json::Value::Null => None,
-        serde_json::Value::Array(_) => None,
+        serde_json::Value::Array(jitems) => {
+            let mut items = Vec::with_capacity(jitems.len());
+            for jv in jitems {
+                items.push(json_to_value(jv)?);
+            }
+            Some(Value::Array(items))
+        }
         serde_json::Value::Object(jprops) => {
             let mut props = BTreeMap::<String, Value>::new();
             for (k, jv) in jprops {
@@ -120,20 +186,93 @@ fn json_to_value(v: serde_json::Value) -> Option<Value> {
     }
 }
 
+/// The inverse of [`json_to_value`]: renders a `Value` as the plain JSON
+/// scalar an MQTT payload expects, rather than through `Value`'s own
+/// variant-tagged `Serialize` impl (which exists for [`Code::write_to`]'s
+/// bytecode persistence, not for the wire). Variants that never appear in an
+/// object literal a user actually writes (`Duration`, `Time`, `Jump`,
+/// `Fault`) have no sensible JSON scalar, so they serialize as `null`.
+fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Str(s) | Value::Path(s) => serde_json::Value::String(s.clone()),
+        Value::Integer(i) => serde_json::Value::from(*i),
+        Value::Float(f) => serde_json::Number::from_f64(*f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Object(props) => serde_json::Value::Object(
+            props
+                .iter()
+                .map(|(k, v)| (k.clone(), value_to_json(v)))
+                .collect(),
+        ),
+        Value::Array(items) => serde_json::Value::Array(items.iter().map(value_to_json).collect()),
+        Value::Duration(_) | Value::Time(_) | Value::Jump(_) | Value::Fault(_) => {
+            serde_json::Value::Null
+        }
+    }
+}
+
 impl TryFrom<Expr> for Value {
-    type Error = anyhow::Error;
+    type Error = CompileError;
 
     fn try_from(value: Expr) -> std::result::Result<Self, Self::Error> {
-        match value {
-            Expr::String(s) => Ok(Self::Str(s)),
-            Expr::Duration(d) => {
-                let s = d.strip_suffix("s").unwrap();
-                let duration = Duration::from_secs(s.parse().unwrap());
-                Ok(Value::Duration(duration))
+        let span = value.span;
+        match value.kind {
+            ExprKind::String(s) => Ok(Self::Str(s)),
+            ExprKind::Duration(d) => {
+                // Parses one or more `<amount><unit>` components (`s`, `m`,
+                // `h`, `d`) back to back, e.g. `5s`, `2h`, `1d`, or the
+                // combined `1h30m` — summing each component's seconds
+                // rather than just handling a single trailing `s` the way
+                // this used to.
+                let malformed = |reason: &str| {
+                    CompileError::new(span, format!("malformed duration literal '{d}': {reason}"))
+                };
+                let mut total_secs: u64 = 0;
+                let mut rest = d.as_str();
+                if rest.is_empty() {
+                    return Err(malformed("expected e.g. '5s', '2h', or '1h30m'"));
+                }
+                while !rest.is_empty() {
+                    let digits_end = rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .ok_or_else(|| malformed("missing a unit suffix (s/m/h/d)"))?;
+                    if digits_end == 0 {
+                        return Err(malformed("expected a number before the unit"));
+                    }
+                    let amount: u64 = rest[..digits_end].parse().map_err(|_| {
+                        malformed(&format!("'{}' is not a whole number", &rest[..digits_end]))
+                    })?;
+                    let unit = rest[digits_end..]
+                        .chars()
+                        .next()
+                        .expect("checked non-empty");
+                    let unit_secs: u64 = match unit {
+                        's' => 1,
+                        'm' => 60,
+                        'h' => 3600,
+                        'd' => 86400,
+                        _ => {
+                            return Err(malformed(&format!(
+                                "unknown unit '{unit}', expected one of s/m/h/d"
+                            )))
+                        }
+                    };
+                    total_secs = amount
+                        .checked_mul(unit_secs)
+                        .and_then(|secs| total_secs.checked_add(secs))
+                        .ok_or_else(|| malformed("overflows"))?;
+                    rest = &rest[digits_end + unit.len_utf8()..];
+                }
+                Ok(Value::Duration(Duration::from_secs(total_secs)))
             }
-            Expr::Time(t) => match t.as_str() {
+            ExprKind::Time(t) => match t.as_str() {
                 "sunrise" => Ok(Value::Time(TimeOfDay::Sunrise)),
                 "sunset" => Ok(Value::Time(TimeOfDay::Sunset)),
+                "dawn" => Ok(Value::Time(TimeOfDay::Dawn)),
+                "dusk" => Ok(Value::Time(TimeOfDay::Dusk)),
+                "solar_noon" => Ok(Value::Time(TimeOfDay::SolarNoon)),
                 _ => {
                     let mut hours = 0;
                     let time = if let Some(time) = t.strip_suffix("PM") {
@@ -142,49 +281,62 @@ impl TryFrom<Expr> for Value {
                     } else if let Some(time) = t.strip_suffix("AM") {
                         time
                     } else {
-                        panic!("parser failed to enforce AM/PM ending to time")
+                        return Err(CompileError::new(
+                            span,
+                            format!("malformed time literal '{t}': expected a trailing AM/PM"),
+                        ));
                     };
-                    let parts: Vec<&str> = time.split(":").collect();
+                    let parts: Vec<&str> = time.split(':').collect();
                     if parts.len() != 2 {
-                        panic!("parser failed to HH:MM time format")
+                        return Err(CompileError::new(
+                            span,
+                            format!("malformed time literal '{t}': expected HH:MM"),
+                        ));
                     }
-                    let h: u32 = parts
-                        .first()
-                        .unwrap()
-                        .parse()
-                        .expect("parser failed to enforce integer hours");
+                    let h: u32 = parts[0].parse().map_err(|_| {
+                        CompileError::new(
+                            span,
+                            format!("malformed time literal '{t}': '{}' is not an hour", parts[0]),
+                        )
+                    })?;
                     if h == hours {
                         // 12PM is noon
                         hours = 0;
                     }
-                    let m: u32 = parts
-                        .last()
-                        .unwrap()
-                        .parse()
-                        .expect("parser failed to enforce integer minutes");
+                    let m: u32 = parts[1].parse().map_err(|_| {
+                        CompileError::new(
+                            span,
+                            format!("malformed time literal '{t}': '{}' is not a minute", parts[1]),
+                        )
+                    })?;
 
                     Ok(Value::Time(TimeOfDay::HM(hours + h, m)))
                 }
             },
-            Expr::Float(n) => Ok(Value::Float(n)),
-            Expr::Boolean(n) => Ok(Value::Bool(n)),
-            Expr::Integer(n) => Ok(Value::Integer(n)),
-            Expr::Object(props) => {
+            ExprKind::Float(n) => Ok(Value::Float(n)),
+            ExprKind::Boolean(n) => Ok(Value::Bool(n)),
+            ExprKind::Integer(n) => Ok(Value::Integer(n)),
+            ExprKind::Object(props) => {
                 let mut properties = BTreeMap::new();
                 for (key, expr) in props {
                     properties.insert(key, expr.try_into()?);
                 }
                 Ok(Value::Object(properties))
             }
-            _ => Err(anyhow!("expression is not a literal value")),
+            _ => Err(CompileError::new(span, "expression is not a literal value")),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TimeOfDay {
     Sunrise,
     Sunset,
+    /// Start of civil twilight, i.e. sunrise-side of [`crate::sun::ZENITH_CIVIL`].
+    Dawn,
+    /// End of civil twilight, i.e. sunset-side of [`crate::sun::ZENITH_CIVIL`].
+    Dusk,
+    SolarNoon,
     HM(u32, u32),
 }
 
@@ -193,12 +345,15 @@ impl Display for TimeOfDay {
         match self {
             TimeOfDay::Sunrise => f.write_str("sunrise"),
             TimeOfDay::Sunset => f.write_str("sunset"),
+            TimeOfDay::Dawn => f.write_str("dawn"),
+            TimeOfDay::Dusk => f.write_str("dusk"),
+            TimeOfDay::SolarNoon => f.write_str("solar_noon"),
             TimeOfDay::HM(h, m) => write!(f, "{}:{}", h, m),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Instruction {
     Constant(usize),
     Print,
@@ -210,6 +365,23 @@ pub enum Instruction {
     JmpNot(usize),
     Call,
     Return,
+    /// Invokes a [`crate::ast::StmtKind::Func`]: pops the `Value::Jump`
+    /// target (pushed by a preceding `Constant`, just like `Call` does for
+    /// `start`/`stop`), leaving `argc` argument values on the stack as the
+    /// callee's parameters, and records a call frame so `ReturnFn` can find
+    /// its way back. A distinct instruction from `Call`/`Return` rather
+    /// than an `argc` added to them: those two already have a "no
+    /// parameters, no result" contract baked into every
+    /// `scene`/`sequence`/`start`/`stop` call site in this tree, and giving
+    /// them a result-and-argc convention would mean re-auditing every one
+    /// of those existing sites.
+    CallFn(usize),
+    /// Ends a `func` body: pops the result value left by the preceding
+    /// expression, pops the `argc` argument values recorded by the
+    /// matching `CallFn`, then pushes the result back — leaving exactly
+    /// one new value on the stack at the call site, like any other
+    /// expression.
+    ReturnFn,
     Term,
     Wait,
     At,
@@ -217,22 +389,208 @@ pub enum Instruction {
     Stop,
     SceneContext,
     Get,
+    Watch,
+    /// Like [`Instruction::Watch`], but the stack holds a path then a
+    /// window `Duration`: subscribes to the path and, through the same
+    /// `watch_stream`/`Await` machinery, re-emits the count of arrivals
+    /// still inside the trailing window every time it changes.
+    CountWatch,
+    Await,
     Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessEqual,
+    GreaterEqual,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
     Index,
+    /// Registers a handler at `ip`: if a later instruction fails before the
+    /// matching `PopHandler` runs, the VM unwinds the stack/call stack back
+    /// to where this ran and jumps to `ip` with the raised fault pushed.
+    PushHandler(usize),
+    /// Deregisters the handler pushed by the most recent `PushHandler` —
+    /// emitted after a `try` block's body completes without failing, so a
+    /// later failure elsewhere in the program doesn't unwind into it.
+    PopHandler,
+    /// Pushes the engine's current time as `Value::Integer` unix seconds.
+    /// `sequence` uses this to capture one shared anchor instant before
+    /// spawning its steps.
+    Now,
+    /// Pops an offset `Duration` then an anchor `Value::Integer` (unix
+    /// seconds) and waits until `anchor + offset`, firing immediately if
+    /// that instant has already passed. Used by `sequence` so a step's fire
+    /// time is measured from the shared anchor rather than from whenever
+    /// its own thread happened to start running.
+    WaitUntil,
+}
+
+/// An error raised while lowering an AST into [`Code`]: the [`Span`] of the
+/// offending source plus a human-readable message. Replaces the panics this
+/// module used to raise on malformed input (undefined identifiers,
+/// malformed duration/time literals, and a handful of internal
+/// "should never happen" invariants around jump backpatching).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub span: Span,
+    pub message: String,
 }
 
-#[derive(Debug, PartialEq)]
+impl CompileError {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Renders `error: <message>` followed by the source line `self.span`
+    /// points into, with a caret underlining the offending column — à la
+    /// the dust `DustError` reporter — instead of a stack trace. Falls back
+    /// to just the message if the span doesn't land inside `source`, or if
+    /// it's `Span::default()` — a placeholder from a node nothing has
+    /// stamped a real position onto yet (see [`crate::ast::Span`]) — since
+    /// underlining column 0 of line 1 would claim a position this error
+    /// doesn't actually have.
+    pub fn render(&self, source: &str) -> String {
+        if self.span == Span::default() {
+            return format!("error: {}", self.message);
+        }
+        let mut line_start = 0;
+        for line in source.split_inclusive('\n') {
+            let line_end = line_start + line.len();
+            if self.span.start >= line_start && self.span.start <= line_end {
+                let col = self.span.start - line_start;
+                let trimmed = line.trim_end_matches('\n');
+                let underline_len = self
+                    .span
+                    .end
+                    .saturating_sub(self.span.start)
+                    .max(1)
+                    .min(trimmed.len().saturating_sub(col).max(1));
+                return format!(
+                    "error: {}\n{}\n{}{}",
+                    self.message,
+                    trimmed,
+                    " ".repeat(col),
+                    "^".repeat(underline_len)
+                );
+            }
+            line_start = line_end;
+        }
+        format!("error: {}", self.message)
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Code {
     pub instructions: Vec<Instruction>,
     pub constants: Vec<Value>,
+    /// Parallel to `instructions`: the span that produced each one, for
+    /// diagnostics raised against already-compiled code. Every entry is
+    /// `Span::default()` until the parser starts stamping real positions
+    /// (see [`crate::ast::Span`]) — the compiler already threads whatever
+    /// span each AST node carries through consistently.
+    pub spans: Vec<Span>,
+    /// Instruction pointer of a trampoline that calls `scene startup`'s body
+    /// and returns, if the program declared one. `VM::run` calls through
+    /// this once before running the rest of the program.
+    pub startup: Option<usize>,
+    /// Instruction pointer of a trampoline that calls `scene idle`'s body
+    /// and returns, if the program declared one. `VM::run` calls through
+    /// this whenever the program goes quiescent (no threads left running).
+    pub idle: Option<usize>,
 }
 
+impl PartialEq for Code {
+    fn eq(&self, other: &Self) -> bool {
+        // `spans` is diagnostic metadata attached to each instruction, not
+        // part of the program's behavior, so two `Code`s that execute
+        // identically compare equal even if they disagree on spans (e.g.
+        // one was hand-built via `from_ast` with no span info at all).
+        self.instructions == other.instructions
+            && self.constants == other.constants
+            && self.startup == other.startup
+            && self.idle == other.idle
+    }
+}
+
+/// Magic bytes stamped at the start of every [`Code::to_bytes`] output, so
+/// [`Code::from_bytes`] can reject a file that isn't bytecode at all before
+/// ever reaching the decoder.
+const CODE_MAGIC: &[u8; 4] = b"DANC";
+
+/// The encoding version [`Code::to_bytes`] writes and [`Code::from_bytes`]
+/// requires an exact match on. Bump this if `Code`'s on-disk shape ever
+/// changes in a way that isn't forward/backward compatible, so a stale
+/// `.danc` artifact fails loudly instead of decoding into garbage.
+const CODE_FORMAT_VERSION: u32 = 1;
+
 impl Code {
     fn new() -> Self {
-        Self {
-            instructions: Vec::new(),
-            constants: Vec::new(),
+        Self::default()
+    }
+
+    /// Encodes this compiled program as a `.danc` artifact: a small header
+    /// (magic bytes plus [`CODE_FORMAT_VERSION`]) followed by the program
+    /// itself, so it can be compiled once on a host machine and shipped to
+    /// a constrained controller that loads it with [`Code::from_bytes`]
+    /// instead of carrying the parser/compiler to run one itself.
+    ///
+    /// The body is JSON — the only serde backend already wired into this
+    /// tree (see [`json_to_value`]/[`value_to_json`]) — rather than a
+    /// dedicated binary codec; the header is what makes the format
+    /// self-describing regardless of what the body turns out to be.
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(CODE_MAGIC);
+        bytes.extend_from_slice(&CODE_FORMAT_VERSION.to_le_bytes());
+        serde_json::to_writer(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    /// Decodes a `.danc` artifact produced by [`Code::to_bytes`], rejecting
+    /// anything whose header doesn't match (not a `.danc` file at all, or
+    /// one written by an incompatible [`CODE_FORMAT_VERSION`]).
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let header_len = CODE_MAGIC.len() + std::mem::size_of::<u32>();
+        if bytes.len() < header_len || &bytes[..CODE_MAGIC.len()] != CODE_MAGIC {
+            return Err(anyhow::anyhow!(
+                "not a recognized .danc bytecode file (missing magic header)"
+            ));
         }
+        let version = u32::from_le_bytes(bytes[CODE_MAGIC.len()..header_len].try_into().unwrap());
+        if version != CODE_FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "unsupported .danc format version {version}, expected {CODE_FORMAT_VERSION}"
+            ));
+        }
+        Ok(serde_json::from_slice(&bytes[header_len..])?)
+    }
+
+    /// Persists this compiled program to `path` so it can later be loaded
+    /// back by [`Code::read_from`] without re-running the parser/compiler —
+    /// e.g. to ship a precompiled automation to a device that shouldn't
+    /// carry the parser.
+    pub fn write_to(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Loads a `Code` previously saved by [`Code::write_to`].
+    pub fn read_from(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
     }
 }
 
@@ -257,240 +615,842 @@ impl<'a> Env<'a> {
             depth: 0,
         }
     }
-    fn get_depth(&self, id: &String) -> usize {
+    /// How many `Pick`-able slots below the top of the stack `id` currently
+    /// sits at, or `None` if it isn't bound in this scope or any enclosing
+    /// one — an explicit absence rather than a magic depth value, since `0`
+    /// is itself sometimes a real answer once looked up through a nested
+    /// scope.
+    fn get_depth(&self, id: &String) -> Option<usize> {
         if let Some(depth) = self.values.get(id) {
-            self.depth - (*depth)
+            Some(self.depth - (*depth))
         } else if let Some(parent) = self.parent {
-            self.depth + parent.get_depth(id)
+            parent.get_depth(id).map(|d| self.depth + d)
         } else {
-            0
+            None
         }
     }
 }
 
 pub struct Interpreter {
     code: Code,
+    /// The span of whichever AST node is currently being lowered, recorded
+    /// into `code.spans` by every `add_instruction` call. Set at the top of
+    /// `interpret_stmt`/`interpret_expr` and restored after recursing into a
+    /// child node, so instructions emitted around a sub-expression/-statement
+    /// (e.g. a loop-back `Jump`) are attributed to the right span.
+    current_span: Span,
+    /// Every `func` declared so far, keyed by name. Call sites are resolved
+    /// through this instead of through `Env`/`Pick`: a function's entry
+    /// point is a compile-time constant, not a value that lives on some
+    /// caller's stack, so a `func` can call itself (or one declared before
+    /// it) without needing a lexical binding that reaches back to its own
+    /// declaration. A `func` declared later in the source is not in here yet
+    /// when the caller's body is compiled.
+    fn_decls: HashMap<String, FnDecl>,
+    /// Maps `constant_key(value)` to that value's index in `code.constants`,
+    /// so `add_constant` can intern in O(1) instead of rescanning the whole
+    /// pool for an equal entry on every call.
+    constant_index: HashMap<String, usize>,
+}
+
+/// A declared `func`'s constant-pool index (holding its entry-point
+/// `Value::Jump`) and parameter count, recorded by [`StmtKind::Func`] and
+/// consulted by every [`ExprKind::Call`] of it.
+struct FnDecl {
+    jump_const: usize,
+    arity: usize,
 }
 
 impl Compile for Interpreter {
     type Output = Code;
+    type Error = CompileError;
 
-    fn from_ast(ast: Stmt) -> Self::Output {
-        let mut interpreter = Interpreter { code: Code::new() };
-        interpreter.interpret_stmt(&mut Env::new(), ast);
+    fn from_ast(ast: Stmt) -> std::result::Result<Self::Output, Self::Error> {
+        let mut interpreter = Interpreter {
+            code: Code::new(),
+            current_span: Span::default(),
+            fn_decls: HashMap::new(),
+            constant_index: HashMap::new(),
+        };
+        interpreter.interpret_stmt(&mut Env::new(), ast)?;
         interpreter.add_instruction(Instruction::Term);
-        interpreter.code
+        Ok(interpreter.code)
     }
 }
 
 impl Interpreter {
-    fn add_constant(&mut self, value: Value) -> usize {
+    /// The key `add_constant`/`backpatch_jump_const` intern `value` under.
+    /// `Value`'s derived `Debug` already combines the variant name with its
+    /// payload (e.g. `Integer(5)` vs. `Float(5.0)`), which is exactly the
+    /// "type tag plus content" a hashable key needs — no separate tag is
+    /// required.
+    fn constant_key(value: &Value) -> String {
+        format!("{value:?}")
+    }
+
+    /// Interns `value` into the constant pool, reusing an existing equal
+    /// entry's index instead of pushing a duplicate (e.g. the `"off"`
+    /// literal in `when <path> is "off"` is only stored once, even though it
+    /// appears twice in the source). Backed by `constant_index` so repeated
+    /// constants resolve in O(1) instead of rescanning `code.constants` on
+    /// every call. Errors if a genuinely distinct pool would grow past
+    /// `u16::MAX` entries, the largest index a `Constant` instruction can
+    /// address.
+    fn add_constant(&mut self, value: Value) -> Result<usize, CompileError> {
+        let key = Self::constant_key(&value);
+        if let Some(&index) = self.constant_index.get(&key) {
+            return Ok(index);
+        }
+        if self.code.constants.len() >= u16::MAX as usize {
+            return Err(CompileError::new(
+                self.current_span,
+                "constant pool overflow: more than u16::MAX distinct constants",
+            ));
+        }
+        let index = self.code.constants.len();
         self.code.constants.push(value);
-        self.code.constants.len() - 1
+        self.constant_index.insert(key, index);
+        Ok(index)
     }
 
     fn add_instruction(&mut self, inst: Instruction) -> usize {
         let position_of_new_instruction = self.code.instructions.len();
         self.code.instructions.push(inst);
+        self.code.spans.push(self.current_span);
         position_of_new_instruction
     }
-    fn interpret_stmt<'a>(&mut self, env: &mut Env<'a>, stmt: Stmt) {
-        match stmt {
-            Stmt::Print(expr) => {
-                self.interpret_expr(env, expr);
+
+    /// Backpatches the `Instruction::Spawn(ip)` at `spawn_ip` to jump past
+    /// the code just emitted for its body. `span` is blamed if `spawn_ip`
+    /// somehow isn't a `Spawn` — an internal compiler invariant that should
+    /// never actually fail, since every caller only backpatches a `Spawn` it
+    /// just emitted itself.
+    fn backpatch_spawn(&mut self, spawn_ip: usize, span: Span) -> Result<(), CompileError> {
+        let l = self.code.instructions.len();
+        match self.code.instructions.get_mut(spawn_ip) {
+            Some(Instruction::Spawn(ip)) => {
+                *ip = l;
+                Ok(())
+            }
+            _ => Err(CompileError::new(
+                span,
+                "internal compiler error: missing spawn instruction to backpatch",
+            )),
+        }
+    }
+
+    /// Backpatches the `Instruction::Jump(ip)` at `jump_ip` to land here.
+    fn backpatch_jump(&mut self, jump_ip: usize, span: Span) -> Result<(), CompileError> {
+        let l = self.code.instructions.len();
+        match self.code.instructions.get_mut(jump_ip) {
+            Some(Instruction::Jump(ip)) => {
+                *ip = l;
+                Ok(())
+            }
+            _ => Err(CompileError::new(
+                span,
+                "internal compiler error: missing jump instruction to backpatch",
+            )),
+        }
+    }
+
+    /// Backpatches the `Instruction::JmpNot(ip)` at `jmp_ip` to land here.
+    fn backpatch_jmp_not(&mut self, jmp_ip: usize, span: Span) -> Result<(), CompileError> {
+        let l = self.code.instructions.len();
+        match self.code.instructions.get_mut(jmp_ip) {
+            Some(Instruction::JmpNot(ip)) => {
+                *ip = l;
+                Ok(())
+            }
+            _ => Err(CompileError::new(
+                span,
+                "internal compiler error: missing jmpnot instruction to backpatch",
+            )),
+        }
+    }
+
+    /// Backpatches the `Value::Jump(ip)` constant at `const_index` to `ip`.
+    ///
+    /// This mutates a pool entry in place after `constant_index` was already
+    /// keyed off its placeholder value, so the stale `Jump(usize::MAX)` key
+    /// (which no longer describes what's stored at `const_index`) is
+    /// dropped and replaced with one for the real `ip` — otherwise a later
+    /// placeholder that happens to share the same value could intern onto
+    /// an entry that's actually already been resolved to something else.
+    fn backpatch_jump_const(
+        &mut self,
+        const_index: usize,
+        ip: usize,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        match self.code.constants.get_mut(const_index) {
+            Some(Value::Jump(slot)) => {
+                let stale_key = Self::constant_key(&Value::Jump(*slot));
+                *slot = ip;
+                if self.constant_index.get(&stale_key) == Some(&const_index) {
+                    self.constant_index.remove(&stale_key);
+                }
+                self.constant_index
+                    .insert(Self::constant_key(&Value::Jump(ip)), const_index);
+                Ok(())
+            }
+            _ => Err(CompileError::new(
+                span,
+                "internal compiler error: missing jump constant to backpatch",
+            )),
+        }
+    }
+
+    /// If `expr` is a guard that is rooted at a single watched path (either
+    /// the path by itself, or an equality comparison against it), returns
+    /// that path and the optional comparison expression. `Stmt::When` uses
+    /// this to decide whether it can subscribe via [`Instruction::Watch`]
+    /// instead of repeatedly re-evaluating the whole expression with `Get`.
+    fn watch_target(expr: &Expr) -> Option<(String, Option<Expr>)> {
+        match &expr.kind {
+            ExprKind::Path(p) => Some((p.clone(), None)),
+            ExprKind::Binary(lhs, BinaryOpcode::Eql, rhs) => match &lhs.kind {
+                ExprKind::Path(p) => Some((p.clone(), Some((**rhs).clone()))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// If `expr` is a guard rooted at `count(<path>, window)` (either by
+    /// itself, or compared against a threshold with `is`), returns the path,
+    /// the window duration, and the optional comparison expression.
+    /// `Stmt::When` uses this to subscribe via [`Instruction::CountWatch`]
+    /// instead of falling back to polling.
+    fn count_watch_target(expr: &Expr) -> Option<(String, Expr, Option<Expr>)> {
+        match &expr.kind {
+            ExprKind::Count(path, window) => match &path.kind {
+                ExprKind::Path(p) => Some((p.clone(), (**window).clone(), None)),
+                _ => None,
+            },
+            ExprKind::Binary(lhs, BinaryOpcode::Eql, rhs) => match &lhs.kind {
+                ExprKind::Count(path, window) => match &path.kind {
+                    ExprKind::Path(p) => {
+                        Some((p.clone(), (**window).clone(), Some((**rhs).clone())))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// If `kind` is a literal a [`Value`] can be read off of directly (no
+    /// further compilation needed), returns that value. Used by
+    /// `ExprKind::Binary`'s constant-folding: only these kinds are cheap and
+    /// infallible to pull a `Value` out of without going through
+    /// `TryFrom<Expr>` (which can fail, e.g. on a malformed duration).
+    fn literal_value(kind: &ExprKind) -> Option<Value> {
+        match kind {
+            ExprKind::Integer(n) => Some(Value::Integer(*n)),
+            ExprKind::Float(f) => Some(Value::Float(*f)),
+            ExprKind::Boolean(b) => Some(Value::Bool(*b)),
+            // A malformed duration literal (e.g. `1x`) falls through to
+            // `None` here rather than folding; `interpret_expr`'s regular,
+            // non-folded path still compiles it and reports the real
+            // `CompileError` through `Value::try_from(Expr)`.
+            ExprKind::Duration(d) => {
+                Value::try_from(Expr::spanned(ExprKind::Duration(d.clone()))).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// A [`StmtKind::Func`] body's last statement becomes its return value
+    /// without needing an explicit `return`, the same way `ExprKind::Block`
+    /// treats its own trailing statement. Rewrites a trailing
+    /// [`StmtKind::Expr`] (bare, or as a [`StmtKind::Block`]'s last entry)
+    /// into a [`StmtKind::Return`] of that expression; anything else (a
+    /// body that already ends with an explicit `return`, or one with no
+    /// trailing expression at all) is left alone and still falls through to
+    /// the `false` fallback `StmtKind::Func`'s codegen emits.
+    fn with_implicit_return(body: Stmt) -> Stmt {
+        match body.kind {
+            StmtKind::Expr(expr) => Stmt::new(StmtKind::Return(expr), body.span),
+            StmtKind::Block(mut stmts) => {
+                if let Some(last) = stmts.pop() {
+                    let last = Self::with_implicit_return(last);
+                    stmts.push(last);
+                }
+                Stmt::new(StmtKind::Block(stmts), body.span)
+            }
+            _ => body,
+        }
+    }
+
+    /// Evaluates `lhs op rhs` at compile time, or `None` if `op` doesn't
+    /// apply to this pair of value types (e.g. adding a bool to an integer,
+    /// or dividing by a literal zero) — in which case the caller falls back
+    /// to emitting the full runtime instruction sequence, which will raise
+    /// the usual VM error for the mismatch.
+    fn fold_binary(lhs: Value, op: BinaryOpcode, rhs: Value) -> Option<Value> {
+        match (op, lhs, rhs) {
+            (BinaryOpcode::Add, Value::Integer(l), Value::Integer(r)) => {
+                Some(Value::Integer(l + r))
+            }
+            (BinaryOpcode::Add, Value::Float(l), Value::Float(r)) => Some(Value::Float(l + r)),
+            (BinaryOpcode::Add, Value::Duration(l), Value::Duration(r)) => {
+                Some(Value::Duration(l + r))
+            }
+            (BinaryOpcode::Sub, Value::Integer(l), Value::Integer(r)) => {
+                Some(Value::Integer(l - r))
+            }
+            (BinaryOpcode::Sub, Value::Float(l), Value::Float(r)) => Some(Value::Float(l - r)),
+            (BinaryOpcode::Sub, Value::Duration(l), Value::Duration(r)) if l >= r => {
+                Some(Value::Duration(l - r))
+            }
+            (BinaryOpcode::Mul, Value::Integer(l), Value::Integer(r)) => {
+                Some(Value::Integer(l * r))
+            }
+            (BinaryOpcode::Mul, Value::Float(l), Value::Float(r)) => Some(Value::Float(l * r)),
+            (BinaryOpcode::Div, Value::Integer(l), Value::Integer(r)) if r != 0 => {
+                Some(Value::Integer(l / r))
+            }
+            (BinaryOpcode::Div, Value::Float(l), Value::Float(r)) => Some(Value::Float(l / r)),
+            (BinaryOpcode::Mod, Value::Integer(l), Value::Integer(r)) if r != 0 => {
+                Some(Value::Integer(l % r))
+            }
+            (BinaryOpcode::Mod, Value::Float(l), Value::Float(r)) => Some(Value::Float(l % r)),
+            (BinaryOpcode::Eql, l, r) => Some(Value::Bool(l == r)),
+            (BinaryOpcode::Ne, l, r) => Some(Value::Bool(l != r)),
+            (BinaryOpcode::Lt, Value::Integer(l), Value::Integer(r)) => Some(Value::Bool(l < r)),
+            (BinaryOpcode::Lt, Value::Float(l), Value::Float(r)) => Some(Value::Bool(l < r)),
+            (BinaryOpcode::Gt, Value::Integer(l), Value::Integer(r)) => Some(Value::Bool(l > r)),
+            (BinaryOpcode::Gt, Value::Float(l), Value::Float(r)) => Some(Value::Bool(l > r)),
+            (BinaryOpcode::Lte, Value::Integer(l), Value::Integer(r)) => Some(Value::Bool(l <= r)),
+            (BinaryOpcode::Lte, Value::Float(l), Value::Float(r)) => Some(Value::Bool(l <= r)),
+            (BinaryOpcode::Gte, Value::Integer(l), Value::Integer(r)) => Some(Value::Bool(l >= r)),
+            (BinaryOpcode::Gte, Value::Float(l), Value::Float(r)) => Some(Value::Bool(l >= r)),
+            (BinaryOpcode::And, Value::Bool(l), Value::Bool(r)) => Some(Value::Bool(l && r)),
+            (BinaryOpcode::Or, Value::Bool(l), Value::Bool(r)) => Some(Value::Bool(l || r)),
+            _ => None,
+        }
+    }
+
+    fn interpret_stmt<'a>(&mut self, env: &mut Env<'a>, stmt: Stmt) -> Result<(), CompileError> {
+        let span = stmt.span;
+        self.current_span = span;
+        match stmt.kind {
+            StmtKind::Print(expr) => {
+                self.interpret_expr(env, expr)?;
+                self.current_span = span;
                 self.add_instruction(Instruction::Print);
             }
-            Stmt::Let(id, expr) => {
+            StmtKind::Let(id, expr) => {
                 // Compute the value and place it on the stack
-                self.interpret_expr(env, expr);
+                self.interpret_expr(env, expr)?;
                 env.values.insert(id, env.depth);
                 env.depth += 1
             }
-            Stmt::Block(stmts) => {
+            StmtKind::Block(stmts) => {
                 let mut block_env = env.nest();
                 for s in stmts {
-                    self.interpret_stmt(&mut block_env, s);
+                    self.interpret_stmt(&mut block_env, s)?;
                 }
+                self.current_span = span;
                 for _ in 0..block_env.depth {
                     self.add_instruction(Instruction::Pop);
                 }
             }
-            Stmt::When(expr, stmt) => {
+            StmtKind::When(expr, stmt) => {
                 let spawn_ip = self.add_instruction(Instruction::Spawn(usize::MAX));
-                // Add expr
-                self.interpret_expr(env, expr);
-                // Add Conditional Jump
-                self.add_instruction(Instruction::JmpNot(spawn_ip as usize + 1));
-                // Add stmt
-                self.interpret_stmt(env, *stmt);
-                // Loop the spawned thread back to the beginning
-                self.add_instruction(Instruction::Jump(spawn_ip as usize + 1));
-
-                // backpatch the spawn jump pointer
-                let l = self.code.instructions.len();
-                if let Some(Instruction::Spawn(ip)) =
-                    self.code.instructions.get_mut(spawn_ip as usize)
-                {
-                    *ip = l;
-                } else {
-                    panic!("missing spawn instruction")
+                match Self::count_watch_target(&expr) {
+                    Some((path, window, cmp)) => {
+                        let path_idx = self.add_constant(Value::Path(path))?;
+                        self.add_instruction(Instruction::Constant(path_idx));
+                        let window_idx = self.add_constant(window.try_into()?)?;
+                        self.add_instruction(Instruction::Constant(window_idx));
+                        self.add_instruction(Instruction::CountWatch);
+                        let loop_top = self.add_instruction(Instruction::Await);
+                        if let Some(rhs) = cmp {
+                            self.interpret_expr(env, rhs)?;
+                            self.current_span = span;
+                            self.add_instruction(Instruction::Equal);
+                        }
+                        self.add_instruction(Instruction::JmpNot(loop_top));
+                        self.interpret_stmt(env, *stmt)?;
+                        self.current_span = span;
+                        self.add_instruction(Instruction::Jump(loop_top));
+                    }
+                    None => match Self::watch_target(&expr) {
+                        Some((path, cmp)) => {
+                            // The guard starts with a watched path, so subscribe
+                            // to it and only re-check the guard when a new value
+                            // actually arrives, instead of busy-polling `get`.
+                            let const_index = self.add_constant(Value::Path(path))?;
+                            self.add_instruction(Instruction::Constant(const_index));
+                            self.add_instruction(Instruction::Watch);
+                            let loop_top = self.add_instruction(Instruction::Await);
+                            if let Some(rhs) = cmp {
+                                self.interpret_expr(env, rhs)?;
+                                self.current_span = span;
+                                self.add_instruction(Instruction::Equal);
+                            }
+                            self.add_instruction(Instruction::JmpNot(loop_top));
+                            self.interpret_stmt(env, *stmt)?;
+                            self.current_span = span;
+                            self.add_instruction(Instruction::Jump(loop_top));
+                        }
+                        None => {
+                            // The guard doesn't resolve to a single watched path
+                            // (e.g. it compares two computed expressions), so fall
+                            // back to re-evaluating the whole expression, which
+                            // may itself poll via `Get`.
+                            self.interpret_expr(env, expr)?;
+                            self.current_span = span;
+                            self.add_instruction(Instruction::JmpNot(spawn_ip + 1));
+                            self.interpret_stmt(env, *stmt)?;
+                            self.current_span = span;
+                            self.add_instruction(Instruction::Jump(spawn_ip + 1));
+                        }
+                    },
                 }
+
+                self.backpatch_spawn(spawn_ip, span)?;
             }
-            Stmt::Wait(expr, stmt) => {
+            StmtKind::Wait(expr, stmt) => {
                 let spawn_ip = self.add_instruction(Instruction::Spawn(usize::MAX));
                 // Add expr
-                self.interpret_expr(env, expr);
+                self.interpret_expr(env, expr)?;
+                self.current_span = span;
                 // Wait, creates a promise
                 self.add_instruction(Instruction::Wait);
                 // Add stmt
-                self.interpret_stmt(env, *stmt);
+                self.interpret_stmt(env, *stmt)?;
+                self.current_span = span;
                 // Terminate the spawned thread
                 self.add_instruction(Instruction::Term);
 
-                // backpatch the spawn jump pointer
-                let l = self.code.instructions.len();
-                if let Some(Instruction::Spawn(ip)) =
-                    self.code.instructions.get_mut(spawn_ip as usize)
-                {
-                    *ip = l;
-                } else {
-                    panic!("missing spawn instruction")
-                }
+                self.backpatch_spawn(spawn_ip, span)?;
             }
-            Stmt::Set(path, expr) => {
-                let const_index = self.add_constant(Value::Path(path));
+            StmtKind::Every(expr, stmt) => {
+                // Same spawned-thread shape as `Wait`, except the body loops
+                // back to re-wait instead of falling through to `Term`, so
+                // the spawned thread keeps re-arming itself on the interval.
+                let spawn_ip = self.add_instruction(Instruction::Spawn(usize::MAX));
+                let loop_top = self.code.instructions.len();
+                // Add expr
+                self.interpret_expr(env, expr)?;
+                self.current_span = span;
+                // Wait, creates a promise
+                self.add_instruction(Instruction::Wait);
+                // Add stmt
+                self.interpret_stmt(env, *stmt)?;
+                self.current_span = span;
+                // Loop back instead of terminating, so the interval repeats.
+                self.add_instruction(Instruction::Jump(loop_top));
+
+                self.backpatch_spawn(spawn_ip, span)?;
+            }
+            StmtKind::Set(path, expr) => {
+                let const_index = self.add_constant(Value::Path(path))?;
                 self.add_instruction(Instruction::Constant(const_index));
                 // Add expr
-                self.interpret_expr(env, expr);
+                self.interpret_expr(env, expr)?;
+                self.current_span = span;
                 // Watch, creates a promise
                 self.add_instruction(Instruction::Set);
             }
-            Stmt::Expr(expr) => {
-                self.interpret_expr(env, expr);
+            StmtKind::Expr(expr) => {
+                self.interpret_expr(env, expr)?;
+                self.current_span = span;
                 self.add_instruction(Instruction::Pop);
             }
-            Stmt::Scene(id, stmt) => {
+            StmtKind::Scene(id, stmt) => {
                 // Scenes are an implicit definition of two functions:
                 // a start and a stop function.
-                env.values.insert(id.clone(), env.depth);
+                let scene_name = id.clone();
+                env.values.insert(id, env.depth);
                 env.depth += 1;
                 let start_jump_const =
-                    self.add_constant(Value::Jump(self.code.instructions.len() + 3));
+                    self.add_constant(Value::Jump(self.code.instructions.len() + 3))?;
                 self.add_instruction(Instruction::Constant(start_jump_const));
 
-                env.values.insert(id + " stop", env.depth);
+                env.values.insert(scene_name.clone() + " stop", env.depth);
                 env.depth += 1;
-                let stop_jump_const = self.add_constant(Value::Jump(usize::MAX)); // we need to backpatch this jump location
+                let stop_jump_const = self.add_constant(Value::Jump(usize::MAX))?; // we need to backpatch this jump location
                 self.add_instruction(Instruction::Constant(stop_jump_const));
 
                 let continue_jump = self.add_instruction(Instruction::Jump(usize::MAX)); // we need to backpatch this jump location
 
                 // Add scene body
                 self.add_instruction(Instruction::SceneContext);
-                self.interpret_stmt(env, *stmt);
+                self.interpret_stmt(env, *stmt)?;
+                self.current_span = span;
                 self.add_instruction(Instruction::Return);
 
                 // Add scene stop body
                 let stop_jump_ip = self.add_instruction(Instruction::Stop);
                 self.add_instruction(Instruction::Return);
 
-                // Backpatch jump constant
-                if let Some(Value::Jump(ip)) = self.code.constants.get_mut(stop_jump_const as usize)
-                {
-                    *ip = stop_jump_ip as usize;
-                } else {
-                    panic!("missing stop jump value")
+                self.backpatch_jump_const(stop_jump_const, stop_jump_ip, span)?;
+                self.backpatch_jump(continue_jump, span)?;
+
+                // `startup` and `idle` are scenes by convention, not syntax:
+                // VM::run calls through these trampolines itself (once at
+                // startup, and whenever the program goes quiescent for
+                // idle) rather than requiring an explicit `start` statement.
+                if scene_name == "startup" || scene_name == "idle" {
+                    let trampoline_ip = self.add_instruction(Instruction::Constant(start_jump_const));
+                    self.add_instruction(Instruction::Call);
+                    self.add_instruction(Instruction::Term);
+                    if scene_name == "startup" {
+                        self.code.startup = Some(trampoline_ip);
+                    } else {
+                        self.code.idle = Some(trampoline_ip);
+                    }
                 }
+            }
+            StmtKind::Try(body, name, handler) => {
+                let push_ip = self.add_instruction(Instruction::PushHandler(usize::MAX));
+                self.interpret_stmt(env, *body)?;
+                self.current_span = span;
+                self.add_instruction(Instruction::PopHandler);
+                let skip_handler = self.add_instruction(Instruction::Jump(usize::MAX));
 
-                // Backpatch the continue jump pointer
-                let l = self.code.instructions.len();
-                if let Some(Instruction::Jump(ip)) = self.code.instructions.get_mut(continue_jump) {
-                    *ip = l;
-                } else {
-                    panic!("missing continue jump instruction")
+                // Backpatch `PushHandler` to land here: the VM jumps to this
+                // ip with the raised fault already pushed, if anything in
+                // the body above fails before `PopHandler` runs.
+                let catch_ip = self.code.instructions.len();
+                match self.code.instructions.get_mut(push_ip) {
+                    Some(Instruction::PushHandler(ip)) => *ip = catch_ip,
+                    _ => {
+                        return Err(CompileError::new(
+                            span,
+                            "internal compiler error: missing push handler instruction to backpatch",
+                        ))
+                    }
+                }
+
+                let mut handler_env = env.nest();
+                handler_env.values.insert(name, handler_env.depth);
+                handler_env.depth += 1;
+                self.interpret_stmt(&mut handler_env, *handler)?;
+                self.current_span = span;
+                for _ in 0..handler_env.depth {
+                    self.add_instruction(Instruction::Pop);
+                }
+
+                self.backpatch_jump(skip_handler, span)?;
+            }
+            StmtKind::Sequence(id, steps) => {
+                // A sequence is a scene whose body, instead of running
+                // straight through, captures one anchor instant and fans
+                // each step out into its own thread waiting on anchor+offset
+                // — so `start`/`stop` work on it exactly as they do on a
+                // scene, including `stop` cancelling every still-pending step.
+                env.values.insert(id.clone(), env.depth);
+                env.depth += 1;
+                let start_jump_const =
+                    self.add_constant(Value::Jump(self.code.instructions.len() + 3))?;
+                self.add_instruction(Instruction::Constant(start_jump_const));
+
+                env.values.insert(id + " stop", env.depth);
+                env.depth += 1;
+                let stop_jump_const = self.add_constant(Value::Jump(usize::MAX))?;
+                self.add_instruction(Instruction::Constant(stop_jump_const));
+
+                let continue_jump = self.add_instruction(Instruction::Jump(usize::MAX));
+
+                self.add_instruction(Instruction::SceneContext);
+                self.add_instruction(Instruction::Now);
+                let mut seq_env = env.nest();
+                seq_env
+                    .values
+                    .insert("sequence anchor".to_string(), seq_env.depth);
+                seq_env.depth += 1;
+                for (offset, action) in steps {
+                    let spawn_ip = self.add_instruction(Instruction::Spawn(usize::MAX));
+                    self.interpret_expr(
+                        &mut seq_env,
+                        Expr::spanned(ExprKind::Ident("sequence anchor".to_string())),
+                    )?;
+                    self.interpret_expr(&mut seq_env, offset)?;
+                    self.current_span = span;
+                    self.add_instruction(Instruction::WaitUntil);
+                    self.interpret_stmt(&mut seq_env, action)?;
+                    self.current_span = span;
+                    self.add_instruction(Instruction::Term);
+
+                    self.backpatch_spawn(spawn_ip, span)?;
                 }
+                self.add_instruction(Instruction::Pop); // drop the anchor
+                self.add_instruction(Instruction::Return);
+
+                // Add sequence stop body
+                let stop_jump_ip = self.add_instruction(Instruction::Stop);
+                self.add_instruction(Instruction::Return);
+
+                self.backpatch_jump_const(stop_jump_const, stop_jump_ip, span)?;
+                self.backpatch_jump(continue_jump, span)?;
             }
-            Stmt::Start(id) => {
-                self.interpret_expr(env, Expr::Ident(id));
+            StmtKind::Start(id) => {
+                self.interpret_expr(env, Expr::spanned(ExprKind::Ident(id)))?;
+                self.current_span = span;
                 self.add_instruction(Instruction::Call);
             }
-            Stmt::Stop(id) => {
-                self.interpret_expr(env, Expr::Ident(id + " stop"));
+            StmtKind::Stop(id) => {
+                self.interpret_expr(env, Expr::spanned(ExprKind::Ident(id + " stop")))?;
+                self.current_span = span;
                 self.add_instruction(Instruction::Call);
             }
-            Stmt::At(expr, stmt) => {
+            StmtKind::At(expr, stmt) => {
                 let spawn_ip = self.add_instruction(Instruction::Spawn(usize::MAX));
-                self.interpret_expr(env, expr);
+                self.interpret_expr(env, expr)?;
+                self.current_span = span;
                 self.add_instruction(Instruction::At);
-                self.interpret_stmt(env, *stmt);
+                self.interpret_stmt(env, *stmt)?;
+                self.current_span = span;
 
                 // Loop the spawned thread back to the beginning
-                self.add_instruction(Instruction::Jump(spawn_ip as usize + 1));
+                self.add_instruction(Instruction::Jump(spawn_ip + 1));
 
-                // backpatch the spawn jump pointer
-                let l = self.code.instructions.len();
-                if let Some(Instruction::Spawn(ip)) =
-                    self.code.instructions.get_mut(spawn_ip as usize)
-                {
-                    *ip = l;
-                } else {
-                    panic!("missing spawn instruction")
+                self.backpatch_spawn(spawn_ip, span)?;
+            }
+            StmtKind::Func(name, params, body) => {
+                let jump_const = self.add_constant(Value::Jump(usize::MAX))?; // backpatched below
+                self.fn_decls.insert(
+                    name,
+                    FnDecl {
+                        jump_const,
+                        arity: params.len(),
+                    },
+                );
+                // The function's own code is emitted inline, right after its
+                // declaration (same trick as `scene`), so normal top-to-
+                // bottom flow must skip over it.
+                let skip_jump = self.add_instruction(Instruction::Jump(usize::MAX));
+
+                let entry_ip = self.code.instructions.len();
+                self.backpatch_jump_const(jump_const, entry_ip, span)?;
+
+                // A function body does not close over the caller's scope —
+                // its parameters are its only bindings — so it compiles
+                // against a fresh `Env` rather than `env.nest()`.
+                let mut fn_env = Env::new();
+                for param in params {
+                    fn_env.values.insert(param, fn_env.depth);
+                    fn_env.depth += 1;
                 }
+                self.interpret_stmt(&mut fn_env, Self::with_implicit_return(*body))?;
+                self.current_span = span;
+                // Every reachable `return` already emits its own `ReturnFn`;
+                // this is just a safety net for a body that falls off the
+                // end without hitting one.
+                let fallback = self.add_constant(Value::Bool(false))?;
+                self.add_instruction(Instruction::Constant(fallback));
+                self.add_instruction(Instruction::ReturnFn);
+
+                self.backpatch_jump(skip_jump, span)?;
+            }
+            StmtKind::Return(expr) => {
+                self.interpret_expr(env, expr)?;
+                self.current_span = span;
+                self.add_instruction(Instruction::ReturnFn);
             }
         };
+        Ok(())
     }
-    fn interpret_expr<'a>(&mut self, env: &mut Env<'a>, expr: Expr) {
-        match expr {
-            Expr::Ident(id) => {
-                let depth = env.get_depth(&id);
-                if depth == 0 {
-                    panic!("undefined id");
+    fn interpret_expr<'a>(&mut self, env: &mut Env<'a>, expr: Expr) -> Result<(), CompileError> {
+        let span = expr.span;
+        self.current_span = span;
+        match expr.kind {
+            ExprKind::Ident(id) => match env.get_depth(&id) {
+                Some(depth) => {
+                    self.add_instruction(Instruction::Pick(depth - 1));
                 }
-                self.add_instruction(Instruction::Pick(depth - 1));
-            }
-            Expr::Binary(lhs, op, rhs) => {
-                self.interpret_expr(env, *lhs);
-                self.interpret_expr(env, *rhs);
-                match op {
-                    BinaryOpcode::Eql => self.add_instruction(Instruction::Equal),
-                    _ => todo!(),
+                None => {
+                    return Err(CompileError::new(
+                        span,
+                        format!("undefined identifier '{id}'"),
+                    ));
+                }
+            },
+            ExprKind::Binary(lhs, op, rhs) => {
+                // If both sides are literals, fold at compile time so e.g.
+                // `1 + 2` emits a single `Constant` instead of a full
+                // lhs/rhs/op instruction sequence.
+                if let (Some(l), Some(r)) =
+                    (Self::literal_value(&lhs.kind), Self::literal_value(&rhs.kind))
+                {
+                    if let Some(folded) = Self::fold_binary(l, op, r) {
+                        let const_index = self.add_constant(folded)?;
+                        self.add_instruction(Instruction::Constant(const_index));
+                        return Ok(());
+                    }
+                }
+                if matches!(op, BinaryOpcode::And | BinaryOpcode::Or) {
+                    // Short-circuit: `rhs` only runs if `lhs` didn't already
+                    // decide the result, so a side-effecting operand on
+                    // either side of `&&`/`||` isn't evaluated needlessly.
+                    // `JmpNot` pops its condition, so unlike the operators
+                    // below, `lhs` doesn't linger on the stack underneath
+                    // `rhs` and `rhs` compiles against `env` unchanged.
+                    self.interpret_expr(env, *lhs)?;
+                    self.current_span = span;
+                    let short_circuit_jump = self.add_instruction(Instruction::JmpNot(usize::MAX));
+                    match op {
+                        BinaryOpcode::And => {
+                            // lhs was true: the result is whatever rhs is.
+                            self.interpret_expr(env, *rhs)?;
+                            self.current_span = span;
+                            let end_jump = self.add_instruction(Instruction::Jump(usize::MAX));
+                            self.backpatch_jmp_not(short_circuit_jump, span)?;
+                            let const_index = self.add_constant(Value::Bool(false))?;
+                            self.add_instruction(Instruction::Constant(const_index));
+                            self.backpatch_jump(end_jump, span)?;
+                        }
+                        BinaryOpcode::Or => {
+                            // lhs was true: short-circuit to `true` without
+                            // evaluating rhs at all.
+                            let const_index = self.add_constant(Value::Bool(true))?;
+                            self.add_instruction(Instruction::Constant(const_index));
+                            let end_jump = self.add_instruction(Instruction::Jump(usize::MAX));
+                            self.backpatch_jmp_not(short_circuit_jump, span)?;
+                            self.interpret_expr(env, *rhs)?;
+                            self.current_span = span;
+                            self.backpatch_jump(end_jump, span)?;
+                        }
+                        _ => unreachable!(),
+                    }
+                    return Ok(());
+                }
+                self.interpret_expr(env, *lhs)?;
+                // `rhs` must see an env that accounts for the one value
+                // `lhs` just left sitting on the stack below it — otherwise
+                // an identifier referenced in `rhs` would resolve to the
+                // wrong depth (e.g. picking up `lhs`'s pushed value instead
+                // of itself). Nest exactly like `ExprKind::As` does for its
+                // `init` binding, just without a name since this slot isn't
+                // addressable.
+                let mut rhs_env = env.nest();
+                rhs_env.depth += 1;
+                self.interpret_expr(&mut rhs_env, *rhs)?;
+                self.current_span = span;
+                let inst = match op {
+                    BinaryOpcode::Add => Instruction::Add,
+                    BinaryOpcode::Sub => Instruction::Sub,
+                    BinaryOpcode::Mul => Instruction::Mul,
+                    BinaryOpcode::Div => Instruction::Div,
+                    BinaryOpcode::Mod => Instruction::Mod,
+                    BinaryOpcode::Eql => Instruction::Equal,
+                    BinaryOpcode::Ne => Instruction::NotEqual,
+                    BinaryOpcode::Lt => Instruction::LessThan,
+                    BinaryOpcode::Gt => Instruction::GreaterThan,
+                    BinaryOpcode::Lte => Instruction::LessEqual,
+                    BinaryOpcode::Gte => Instruction::GreaterEqual,
+                    BinaryOpcode::And | BinaryOpcode::Or => unreachable!("handled above"),
                 };
+                self.add_instruction(inst);
             }
-            Expr::Path(p) => {
-                let path = self.add_constant(Value::Path(p));
+            ExprKind::Path(p) => {
+                let path = self.add_constant(Value::Path(p))?;
                 self.add_instruction(Instruction::Constant(path));
                 self.add_instruction(Instruction::Get);
             }
-            Expr::String(_)
-            | Expr::Duration(_)
-            | Expr::Time(_)
-            | Expr::Float(_)
-            | Expr::Boolean(_)
-            | Expr::Integer(_)
-            | Expr::Object(_) => {
-                let const_index = self.add_constant(expr.try_into().unwrap());
+            kind @ (ExprKind::String(_)
+            | ExprKind::Duration(_)
+            | ExprKind::Time(_)
+            | ExprKind::Float(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::Integer(_)
+            | ExprKind::Object(_)) => {
+                let value: Value = Expr::new(kind, span).try_into()?;
+                let const_index = self.add_constant(value)?;
                 self.add_instruction(Instruction::Constant(const_index));
             }
-            Expr::As(init, id, cont) => {
+            ExprKind::As(init, id, cont) => {
                 // Compute the value and place it on the stack
-                self.interpret_expr(env, *init);
+                self.interpret_expr(env, *init)?;
 
                 // Create new scope block for this value
                 let mut block_env = env.nest();
                 block_env.values.insert(id, block_env.depth);
                 block_env.depth += 1;
-                self.interpret_expr(&mut block_env, *cont);
+                self.interpret_expr(&mut block_env, *cont)?;
+                self.current_span = span;
                 self.add_instruction(Instruction::Swap);
                 self.add_instruction(Instruction::Pop);
             }
-            Expr::Index(expr, prop) => {
+            ExprKind::Block(mut stmts) => {
+                // Unlike `StmtKind::Block` (which always discards its
+                // value), a block used as an expression keeps its final
+                // statement's value: everything but the last statement
+                // runs for effect/bindings as usual, then the last
+                // statement's expression is computed and the block's own
+                // locals are popped out from underneath it, generalizing
+                // the single Swap+Pop pair `ExprKind::As` uses to one pair
+                // per local this block introduced.
+                let last = stmts
+                    .pop()
+                    .ok_or_else(|| CompileError::new(span, "block expression must not be empty"))?;
+                let StmtKind::Expr(value) = last.kind else {
+                    return Err(CompileError::new(
+                        last.span,
+                        "block expression must end with an expression",
+                    ));
+                };
+                let mut block_env = env.nest();
+                for s in stmts {
+                    self.interpret_stmt(&mut block_env, s)?;
+                }
+                self.interpret_expr(&mut block_env, value)?;
+                self.current_span = span;
+                for _ in 0..block_env.depth {
+                    self.add_instruction(Instruction::Swap);
+                    self.add_instruction(Instruction::Pop);
+                }
+            }
+            ExprKind::Index(expr, prop) => {
                 // Compute the value and place it on the stack
-                self.interpret_expr(env, *expr);
-                let p = self.add_constant(Value::Str(prop));
+                self.interpret_expr(env, *expr)?;
+                self.current_span = span;
+                let p = self.add_constant(Value::Str(prop))?;
                 self.add_instruction(Instruction::Constant(p));
                 self.add_instruction(Instruction::Index);
             }
+            ExprKind::Count(_, _) => {
+                return Err(CompileError::new(
+                    span,
+                    "count() is only supported as a when guard",
+                ))
+            }
+            ExprKind::Call(name, args) => {
+                let decl = self.fn_decls.get(&name).ok_or_else(|| {
+                    CompileError::new(span, format!("undefined function '{name}'"))
+                })?;
+                if decl.arity != args.len() {
+                    return Err(CompileError::new(
+                        span,
+                        format!(
+                            "function '{name}' takes {} argument(s), but {} were given",
+                            decl.arity,
+                            args.len()
+                        ),
+                    ));
+                }
+                let jump_const = decl.jump_const;
+                let argc = args.len();
+                // Each argument must see an env that accounts for however
+                // many sibling arguments already sit on the stack above it,
+                // same reasoning as `ExprKind::Binary`'s `rhs_env`.
+                let mut call_env = env.nest();
+                for arg in args {
+                    self.interpret_expr(&mut call_env, arg)?;
+                    call_env.depth += 1;
+                }
+                self.current_span = span;
+                self.add_instruction(Instruction::Constant(jump_const));
+                self.add_instruction(Instruction::CallFn(argc));
+            }
         }
+        Ok(())
     }
 }
 
@@ -514,6 +1474,7 @@ mod tests {
                     Instruction::Term,
                 ],
                 constants: vec![Value::Str("hello_world".to_string())],
+                ..Default::default()
             },
             code
         );
@@ -558,6 +1519,7 @@ print z;
                     Value::Str("y".to_string()),
                     Value::Str("z".to_string())
                 ],
+                ..Default::default()
             },
             code
         );
@@ -604,6 +1566,7 @@ let x = "x";
                     Value::Str("y".to_string()),
                     Value::Str("z".to_string())
                 ],
+                ..Default::default()
             },
             code
         );
@@ -646,6 +1609,7 @@ print x;
                     Value::Str("y".to_string()),
                     Value::Str("z".to_string())
                 ],
+                ..Default::default()
             },
             code
         );
@@ -688,11 +1652,70 @@ print x;
                     Value::Str("y".to_string()),
                     Value::Str("z".to_string())
                 ],
+                ..Default::default()
             },
             code
         );
     }
     #[test]
+    fn test_block_expr() {
+        // There's no block-expression syntax in this tree's grammar yet, so
+        // this builds the AST directly instead of going through
+        // `Interpreter::from_source`: `let y = { let x = 1; x };` — `y`'s
+        // initializer is an `ExprKind::Block` whose last statement (`x`)
+        // supplies the value, with `x` itself popped out from underneath
+        // it (the `Swap`/`Pop` pair right after `Pick(0)` below), leaving
+        // only `y` live for `print`.
+        let source_ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Let(
+                "y".to_string(),
+                Expr::spanned(ExprKind::Block(vec![
+                    Stmt::spanned(StmtKind::Let(
+                        "x".to_string(),
+                        Expr::spanned(ExprKind::Integer(1)),
+                    )),
+                    Stmt::spanned(StmtKind::Expr(Expr::spanned(ExprKind::Ident(
+                        "x".to_string(),
+                    )))),
+                ])),
+            )),
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Ident(
+                "y".to_string(),
+            )))),
+        ]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0), // x = 1
+                    Instruction::Pick(0),     // x (the block's value)
+                    Instruction::Swap,
+                    Instruction::Pop,     // drop x, keeping the block's value as y
+                    Instruction::Pick(0), // y
+                    Instruction::Print,
+                    Instruction::Pop, // drop y
+                    Instruction::Term,
+                ],
+                constants: vec![Value::Integer(1)],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_block_expr_requires_trailing_expr() {
+        let source_ast = Stmt::spanned(StmtKind::Let(
+            "y".to_string(),
+            Expr::spanned(ExprKind::Block(vec![Stmt::spanned(StmtKind::Let(
+                "x".to_string(),
+                Expr::spanned(ExprKind::Integer(1)),
+            ))])),
+        ));
+        let err = Interpreter::from_ast(source_ast).unwrap_err();
+        assert!(err.message.contains("must end with an expression"));
+    }
+    #[test]
     fn test_as() {
         let source = r#"
         print 1 as x in x;
@@ -710,6 +1733,7 @@ print x;
                     Instruction::Term,
                 ],
                 constants: vec![Value::Integer(1)],
+                ..Default::default()
             },
             code
         );
@@ -739,6 +1763,37 @@ print x;
                     ]),
                     Value::Str("x".to_string()),
                 ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_array_index() {
+        // There's no array-literal syntax in this tree's grammar yet, so
+        // this builds the AST directly instead of going through
+        // `Interpreter::from_source`. `<arr>.1` reuses the same
+        // `ExprKind::Index` node `obj.prop` does (see `test_index`) — only
+        // the runtime `Value` it resolves against differs, so it compiles
+        // to the same instruction shape.
+        let source_ast = Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Index(
+            Box::new(Expr::spanned(ExprKind::Path("arr".to_string()))),
+            "1".to_string(),
+        ))));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0),
+                    Instruction::Get,
+                    Instruction::Constant(1),
+                    Instruction::Index,
+                    Instruction::Print,
+                    Instruction::Term,
+                ],
+                constants: vec![Value::Path("arr".to_string()), Value::Str("1".to_string()),],
+                ..Default::default()
             },
             code
         );
@@ -753,22 +1808,23 @@ print x;
         assert_eq!(
             Code {
                 instructions: vec![
-                    Instruction::Spawn(9),
+                    Instruction::Spawn(10),
                     Instruction::Constant(0),
-                    Instruction::Get,
+                    Instruction::Watch,
+                    Instruction::Await,
                     Instruction::Constant(1),
                     Instruction::Equal,
-                    Instruction::JmpNot(1),
-                    Instruction::Constant(2),
+                    Instruction::JmpNot(3),
+                    Instruction::Constant(1),
                     Instruction::Print,
-                    Instruction::Jump(1),
+                    Instruction::Jump(3),
                     Instruction::Term,
                 ],
                 constants: vec![
                     Value::Path("path".to_string()),
                     Value::Str("off".to_string()),
-                    Value::Str("off".to_string())
                 ],
+                ..Default::default()
             },
             code
         );
@@ -792,7 +1848,7 @@ print x;
                     Instruction::Swap,
                     Instruction::Pop,
                     Instruction::JmpNot(1),
-                    Instruction::Constant(2),
+                    Instruction::Constant(1),
                     Instruction::Print,
                     Instruction::Jump(1),
                     Instruction::Term,
@@ -800,8 +1856,55 @@ print x;
                 constants: vec![
                     Value::Path("path".to_string()),
                     Value::Str("off".to_string()),
-                    Value::Str("off".to_string())
                 ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_when_count() {
+        // `count(<path>, window)` has no grammar support in this tree yet
+        // (see parser::tests), so this builds the AST directly instead of
+        // going through `Interpreter::from_source`.
+        let ast = Stmt::spanned(StmtKind::When(
+            Expr::spanned(ExprKind::Binary(
+                Box::new(Expr::spanned(ExprKind::Count(
+                    Box::new(Expr::spanned(ExprKind::Path("motion".to_string()))),
+                    Box::new(Expr::spanned(ExprKind::Duration("300s".to_string()))),
+                ))),
+                BinaryOpcode::Eql,
+                Box::new(Expr::spanned(ExprKind::Integer(3))),
+            )),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::String("tripped".to_string()),
+            )))),
+        ));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Spawn(11),
+                    Instruction::Constant(0),
+                    Instruction::Constant(1),
+                    Instruction::CountWatch,
+                    Instruction::Await,
+                    Instruction::Constant(2),
+                    Instruction::Equal,
+                    Instruction::JmpNot(4),
+                    Instruction::Constant(3),
+                    Instruction::Print,
+                    Instruction::Jump(4),
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Path("motion".to_string()),
+                    Value::Duration(Duration::from_secs(300)),
+                    Value::Integer(3),
+                    Value::Str("tripped".to_string()),
+                ],
+                ..Default::default()
             },
             code
         );
@@ -828,6 +1931,103 @@ print x;
                     Value::Duration(Duration::from_secs(1)),
                     Value::Str("done".to_string()),
                 ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_wait_combined_duration_units() {
+        let source = r#"
+        wait 1h30m print "done";
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Spawn(6),
+                    Instruction::Constant(0),
+                    Instruction::Wait,
+                    Instruction::Constant(1),
+                    Instruction::Print,
+                    Instruction::Term,
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Duration(Duration::from_secs(90 * 60)),
+                    Value::Str("done".to_string()),
+                ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_duration_rejects_malformed_literal() {
+        let err = Value::try_from(Expr::spanned(ExprKind::Duration("1x".to_string()))).unwrap_err();
+        assert!(err.message.contains("unknown unit 'x'"));
+
+        let err = Value::try_from(Expr::spanned(ExprKind::Duration("h".to_string()))).unwrap_err();
+        assert!(err.message.contains("expected a number before the unit"));
+    }
+    #[test]
+    fn test_duration_and_time_serialize_to_bytes() {
+        let bytes: Vec<u8> = Value::Duration(Duration::from_secs(90)).try_into().unwrap();
+        assert_eq!(b"90".to_vec(), bytes);
+
+        let bytes: Vec<u8> = Value::Time(TimeOfDay::HM(9, 5)).try_into().unwrap();
+        assert_eq!(b"9:5".to_vec(), bytes);
+    }
+    #[test]
+    fn test_bool_serializes_to_bytes() {
+        // `set [light] 1 < 2;` produces a runtime `Value::Bool`, which isn't
+        // caught by `check` (it only flags *literal* booleans), so this
+        // conversion has to actually work rather than `todo!()`.
+        let bytes: Vec<u8> = Value::Bool(true).try_into().unwrap();
+        assert_eq!(b"true".to_vec(), bytes);
+
+        let bytes: Vec<u8> = Value::Bool(false).try_into().unwrap();
+        assert_eq!(b"false".to_vec(), bytes);
+    }
+    #[test]
+    fn test_fault_serializes_to_bytes() {
+        // `try … on error e { set [log] e; }` binds `e` to a runtime
+        // `Value::Fault`, which `check` has no way to flag (it only
+        // catches *literal* `Set` operands), so this conversion has to
+        // actually work rather than `todo!()`.
+        let bytes: Vec<u8> = Value::Fault(Fault::PathNotFound).try_into().unwrap();
+        assert_eq!(b"PathNotFound".to_vec(), bytes);
+    }
+    #[test]
+    fn test_every() {
+        // `every` has no grammar support in this tree yet (see
+        // parser::tests), so this builds the AST directly instead of going
+        // through `Interpreter::from_source`.
+        let ast = Stmt::spanned(StmtKind::Block(vec![Stmt::spanned(StmtKind::Every(
+            Expr::spanned(ExprKind::Duration("1s".to_string())),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::String("tick".to_string()),
+            )))),
+        ))]));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Spawn(6),
+                    Instruction::Constant(0),
+                    Instruction::Wait,
+                    Instruction::Constant(1),
+                    Instruction::Print,
+                    Instruction::Jump(1),
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Duration(Duration::from_secs(1)),
+                    Value::Str("tick".to_string()),
+                ],
+                ..Default::default()
             },
             code
         );
@@ -851,6 +2051,7 @@ print x;
                     Value::Path("path/to/value".to_string()),
                     Value::Str("on".to_string()),
                 ],
+                ..Default::default()
             },
             code
         );
@@ -885,6 +2086,58 @@ print x;
                     Instruction::Term
                 ],
                 constants: vec![Value::Jump(3), Value::Jump(7), Value::Str("x".to_string()),],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_sequence() {
+        // `sequence` has no grammar support yet in this tree (the
+        // `.lalrpop` source isn't present to extend), so this builds the
+        // AST directly rather than going through `Interpreter::from_source`.
+        let ast = Stmt::spanned(StmtKind::Sequence(
+            "s".to_string(),
+            vec![(
+                Expr::spanned(ExprKind::Duration("0s".to_string())),
+                Stmt::spanned(StmtKind::Set(
+                    "path".to_string(),
+                    Expr::spanned(ExprKind::Integer(1)),
+                )),
+            )],
+        ));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0), // Jump address of sequence start code
+                    Instruction::Constant(1), // Jump address of sequence stop code
+                    Instruction::Jump(17),
+                    Instruction::SceneContext, // Sequence start
+                    Instruction::Now,          // capture the shared anchor
+                    Instruction::Spawn(13),    // step 0
+                    Instruction::Pick(0),      // anchor
+                    Instruction::Constant(2),  // offset
+                    Instruction::WaitUntil,
+                    Instruction::Constant(3), // path
+                    Instruction::Constant(4), // value
+                    Instruction::Set,
+                    Instruction::Term,
+                    Instruction::Pop, // drop the anchor
+                    Instruction::Return,
+                    Instruction::Stop, // Sequence stop
+                    Instruction::Return,
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Jump(3),
+                    Value::Jump(15),
+                    Value::Duration(Duration::from_secs(0)),
+                    Value::Path("path".to_string()),
+                    Value::Integer(1),
+                ],
+                ..Default::default()
             },
             code
         );
@@ -911,6 +2164,7 @@ print x;
                     Value::Time(TimeOfDay::HM(12, 50)),
                     Value::Str("x".to_string()),
                 ],
+                ..Default::default()
             },
             code
         );
@@ -930,6 +2184,7 @@ print x;
                     Instruction::Term,
                 ],
                 constants: vec![Value::Bool(true),],
+                ..Default::default()
             },
             code
         );
@@ -949,6 +2204,7 @@ print x;
                     Instruction::Term,
                 ],
                 constants: vec![Value::Float(7.0),],
+                ..Default::default()
             },
             code
         );
@@ -968,8 +2224,459 @@ print x;
                     Instruction::Term,
                 ],
                 constants: vec![Value::Integer(7),],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_binary_arithmetic_constant_folds() {
+        let source = r#"
+        print 1 + 2;
+        print 2 * 3;
+        print 10 - 4;
+        print 9 / 3;
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0),
+                    Instruction::Print,
+                    Instruction::Constant(1),
+                    Instruction::Print,
+                    Instruction::Constant(1),
+                    Instruction::Print,
+                    Instruction::Constant(0),
+                    Instruction::Print,
+                    Instruction::Term,
+                ],
+                constants: vec![Value::Integer(3), Value::Integer(6)],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_binary_duration_add_sub_constant_folds() {
+        let source = r#"
+        print 30s + 30s;
+        print 90s - 30s;
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0),
+                    Instruction::Print,
+                    Instruction::Constant(0),
+                    Instruction::Print,
+                    Instruction::Term,
+                ],
+                constants: vec![Value::Duration(Duration::from_secs(60))],
+                ..Default::default()
+            },
+            code
+        );
+    }
+
+    #[test]
+    fn test_binary_duration_sub_underflow_does_not_constant_fold() {
+        // `fold_binary` must not evaluate `l - r` itself here: a literal
+        // `Duration` underflow panics (`std::time::Duration`'s `Sub` has no
+        // saturating/checked variant in this match), so the guard falls
+        // back to the ordinary runtime instruction sequence instead,
+        // mirroring the `r != 0` guard on integer division and modulo above.
+        let source = "print 30s - 90s;";
+        let code = Interpreter::from_source(source).unwrap();
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0),
+                    Instruction::Constant(1),
+                    Instruction::Sub,
+                    Instruction::Print,
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Duration(Duration::from_secs(30)),
+                    Value::Duration(Duration::from_secs(90)),
+                ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+
+    #[test]
+    fn test_binary_arithmetic_non_literal() {
+        let source = r#"
+        let x = 1;
+        print x + 2;
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0),
+                    Instruction::Pick(0),
+                    Instruction::Constant(1),
+                    Instruction::Add,
+                    Instruction::Print,
+                    Instruction::Pop,
+                    Instruction::Term,
+                ],
+                constants: vec![Value::Integer(1), Value::Integer(2)],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_binary_comparison_and_logical() {
+        // `!=`, `<`, `>`, `&&`, and `||` have no grammar support in this tree
+        // yet (see parser::tests), so this builds the AST directly instead
+        // of going through `Interpreter::from_source`.
+        let ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Let(
+                "x".to_string(),
+                Expr::spanned(ExprKind::Integer(5)),
+            )),
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Binary(
+                Box::new(Expr::spanned(ExprKind::Binary(
+                    Box::new(Expr::spanned(ExprKind::Ident("x".to_string()))),
+                    BinaryOpcode::Lt,
+                    Box::new(Expr::spanned(ExprKind::Integer(10))),
+                ))),
+                BinaryOpcode::And,
+                Box::new(Expr::spanned(ExprKind::Binary(
+                    Box::new(Expr::spanned(ExprKind::Ident("x".to_string()))),
+                    BinaryOpcode::Ne,
+                    Box::new(Expr::spanned(ExprKind::Integer(0))),
+                ))),
+            )))),
+        ]));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0), // x = 5
+                    Instruction::Pick(0),     // x
+                    Instruction::Constant(1), // 10
+                    Instruction::LessThan,
+                    Instruction::JmpNot(9), // lhs false: skip rhs, push false
+                    Instruction::Pick(0), // x (same depth as above: `JmpNot` popped the `<` result)
+                    Instruction::Constant(2),
+                    Instruction::NotEqual,
+                    Instruction::Jump(10), // lhs true: skip the `false` constant below
+                    Instruction::Constant(3), // false
+                    Instruction::Print,
+                    Instruction::Pop, // drop x
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Integer(5),
+                    Value::Integer(10),
+                    Value::Integer(0),
+                    Value::Bool(false),
+                ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_binary_mod_lte_gte_constant_folds() {
+        // `%`, `<=`, and `>=` have no grammar support in this tree yet (see
+        // parser::tests), so this builds the AST directly instead of going
+        // through `Interpreter::from_source`.
+        let ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Binary(
+                Box::new(Expr::spanned(ExprKind::Integer(7))),
+                BinaryOpcode::Mod,
+                Box::new(Expr::spanned(ExprKind::Integer(3))),
+            )))),
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Binary(
+                Box::new(Expr::spanned(ExprKind::Integer(2))),
+                BinaryOpcode::Lte,
+                Box::new(Expr::spanned(ExprKind::Integer(2))),
+            )))),
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Binary(
+                Box::new(Expr::spanned(ExprKind::Integer(3))),
+                BinaryOpcode::Gte,
+                Box::new(Expr::spanned(ExprKind::Integer(5))),
+            )))),
+        ]));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Constant(0), // 7 % 3
+                    Instruction::Print,
+                    Instruction::Constant(1), // 2 <= 2
+                    Instruction::Print,
+                    Instruction::Constant(2), // 3 >= 5
+                    Instruction::Print,
+                    Instruction::Term,
+                ],
+                constants: vec![Value::Integer(1), Value::Bool(true), Value::Bool(false)],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_try() {
+        // `try`/`on error` has no grammar support yet in this tree (the
+        // `.lalrpop` source isn't present to extend), so this builds the AST
+        // directly rather than going through `Interpreter::from_source`.
+        let ast = Stmt::spanned(StmtKind::Try(
+            Box::new(Stmt::spanned(StmtKind::Set(
+                "path/to/value".to_string(),
+                Expr::spanned(ExprKind::String("on".to_string())),
+            ))),
+            "fault".to_string(),
+            Box::new(Stmt::spanned(StmtKind::Print(Expr::spanned(
+                ExprKind::Ident("fault".to_string()),
+            )))),
+        ));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::PushHandler(6),
+                    Instruction::Constant(0),
+                    Instruction::Constant(1),
+                    Instruction::Set,
+                    Instruction::PopHandler,
+                    Instruction::Jump(9),
+                    Instruction::Pick(0),
+                    Instruction::Print,
+                    Instruction::Pop,
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Path("path/to/value".to_string()),
+                    Value::Str("on".to_string()),
+                ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_undefined_identifier_reports_compile_error() {
+        // One of the cases in this tree where an end user's program can
+        // actually hit a compile error (as opposed to the internal
+        // "should never happen" backpatch invariants): referencing an
+        // identifier nothing bound.
+        let ast = Stmt::spanned(StmtKind::Print(Expr::new(
+            ExprKind::Ident("undefined".to_string()),
+            Span::new(6, 15),
+        )));
+        let err = Interpreter::from_ast(ast).unwrap_err();
+        assert_eq!(Span::new(6, 15), err.span);
+        assert!(err.message.contains("undefined"));
+
+        let rendered = err.render("print undefined;");
+        let expect = expect_test::expect![[r#"
+            error: undefined identifier 'undefined'
+            print undefined;
+                  ^^^^^^^^^
+        "#]];
+        expect.assert_eq(&format!("{rendered}\n"));
+    }
+    #[test]
+    fn test_render_falls_back_to_message_for_default_span() {
+        // `Span::default()` is the placeholder every node carries today (see
+        // `ast::Span`'s doc comment) — it isn't a real position, so `render`
+        // shouldn't underline column 0 of line 1 as if it were one.
+        let err = CompileError::new(Span::default(), "something went wrong");
+        assert_eq!("error: something went wrong", err.render("print 1;"));
+    }
+    #[test]
+    fn test_func_call() {
+        // `func`/call-expression syntax has no grammar support in this tree
+        // yet (see parser::tests), so this builds the AST directly instead
+        // of going through `Interpreter::from_source`.
+        let ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Func(
+                "add".to_string(),
+                vec!["a".to_string(), "b".to_string()],
+                Box::new(Stmt::spanned(StmtKind::Return(Expr::spanned(
+                    ExprKind::Binary(
+                        Box::new(Expr::spanned(ExprKind::Ident("a".to_string()))),
+                        BinaryOpcode::Add,
+                        Box::new(Expr::spanned(ExprKind::Ident("b".to_string()))),
+                    ),
+                )))),
+            )),
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Call(
+                "add".to_string(),
+                vec![
+                    Expr::spanned(ExprKind::Integer(2)),
+                    Expr::spanned(ExprKind::Integer(3)),
+                ],
+            )))),
+        ]));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Jump(7), // skip over "add"'s body
+                    Instruction::Pick(1), // a
+                    Instruction::Pick(1), // b
+                    Instruction::Add,
+                    Instruction::ReturnFn,
+                    Instruction::Constant(1), // false (fallback, in case the body falls through)
+                    Instruction::ReturnFn,
+                    Instruction::Constant(2), // 2
+                    Instruction::Constant(3), // 3
+                    Instruction::Constant(0), // "add"'s entry point, Jump(1)
+                    Instruction::CallFn(2),
+                    Instruction::Print,
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Jump(1),
+                    Value::Bool(false),
+                    Value::Integer(2),
+                    Value::Integer(3),
+                ],
+                ..Default::default()
+            },
+            code
+        );
+    }
+    #[test]
+    fn test_func_implicit_return() {
+        // A one-parameter function whose body is a bare expression
+        // statement (no `return`), mirroring something like
+        // `func dim(level) { level * 2 }`: its last (here, only)
+        // statement's value becomes the return value automatically.
+        let ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Func(
+                "dim".to_string(),
+                vec!["level".to_string()],
+                Box::new(Stmt::spanned(StmtKind::Expr(Expr::spanned(
+                    ExprKind::Binary(
+                        Box::new(Expr::spanned(ExprKind::Ident("level".to_string()))),
+                        BinaryOpcode::Mul,
+                        Box::new(Expr::spanned(ExprKind::Integer(2))),
+                    ),
+                )))),
+            )),
+            Stmt::spanned(StmtKind::Print(Expr::spanned(ExprKind::Call(
+                "dim".to_string(),
+                vec![Expr::spanned(ExprKind::Integer(5))],
+            )))),
+        ]));
+        let code = Interpreter::from_ast(ast).unwrap();
+        log::debug!("code:     {:?}", code);
+        assert_eq!(
+            Code {
+                instructions: vec![
+                    Instruction::Jump(7),     // skip over "dim"'s body
+                    Instruction::Pick(0),     // level
+                    Instruction::Constant(1), // 2
+                    Instruction::Mul,
+                    Instruction::ReturnFn,
+                    Instruction::Constant(2), // false (fallback, unreachable here)
+                    Instruction::ReturnFn,
+                    Instruction::Constant(3), // 5
+                    Instruction::Constant(0), // "dim"'s entry point, Jump(1)
+                    Instruction::CallFn(1),
+                    Instruction::Print,
+                    Instruction::Term,
+                ],
+                constants: vec![
+                    Value::Jump(1),
+                    Value::Integer(2),
+                    Value::Bool(false),
+                    Value::Integer(5),
+                ],
+                ..Default::default()
             },
             code
         );
     }
+    #[test]
+    fn test_call_arity_mismatch_reports_compile_error() {
+        let ast = Stmt::spanned(StmtKind::Block(vec![
+            Stmt::spanned(StmtKind::Func(
+                "add".to_string(),
+                vec!["a".to_string(), "b".to_string()],
+                Box::new(Stmt::spanned(StmtKind::Return(Expr::spanned(
+                    ExprKind::Ident("a".to_string()),
+                )))),
+            )),
+            Stmt::spanned(StmtKind::Print(Expr::new(
+                ExprKind::Call("add".to_string(), vec![Expr::spanned(ExprKind::Integer(1))]),
+                Span::new(10, 20),
+            ))),
+        ]));
+        let err = Interpreter::from_ast(ast).unwrap_err();
+        assert_eq!(Span::new(10, 20), err.span);
+        assert!(err.message.contains("add"));
+        assert!(err.message.contains("2 argument"));
+    }
+    #[test]
+    fn test_code_write_read_round_trip() {
+        // Covers every construct this pass knows how to round-trip:
+        // `when`, `wait`, `set`, `scene`/`start`/`stop`, `at`, and object
+        // indexing.
+        let source = r#"
+let x = 5;
+wait 1s print "done";
+when <path> print "off";
+set [path/to/value] "on";
+scene night { print "dark"; };
+start night;
+stop night;
+at 12:50PM print "x";
+print 1.5;
+print true;
+let o = {x: 1};
+print o.x;
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "jim_test_code_round_trip_{}.jimc",
+            std::process::id()
+        ));
+        code.write_to(&path).unwrap();
+        let reloaded = Code::read_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(code, reloaded);
+    }
+    #[test]
+    fn test_code_to_from_bytes_round_trip() {
+        let source = r#"
+print { a: "hi", b: 3 };
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        let bytes = code.to_bytes().unwrap();
+        assert_eq!(b"DANC", &bytes[..4]);
+        let reloaded = Code::from_bytes(&bytes).unwrap();
+        assert_eq!(code, reloaded);
+    }
+    #[test]
+    fn test_code_from_bytes_rejects_bad_header() {
+        let err = Code::from_bytes(b"not a danc file").unwrap_err();
+        assert!(err.to_string().contains("missing magic header"));
+
+        let mut bytes = Code::new().to_bytes().unwrap();
+        bytes[4..8].copy_from_slice(&99u32.to_le_bytes());
+        let err = Code::from_bytes(&bytes).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unsupported .danc format version 99"));
+    }
 }
@@ -0,0 +1,220 @@
+//! Human-readable rendering of [`crate::compiler::CompileError`]/
+//! [`crate::check::SemanticError`] failures against the original source
+//! text — the ariadne-style "label the span, point at it" report the dust
+//! parser experiment uses, built on top of [`crate::ast::Span`] rather than
+//! a separate position type.
+//!
+//! Every `Span` in this tree is `Span::default()` until the parser starts
+//! stamping real byte offsets (see `Span`'s doc comment — there's no
+//! `.lalrpop` grammar source in this tree to add that to yet); `render`
+//! detects that placeholder and falls back to just the message rather
+//! than claiming a "line 1, column 1" it doesn't actually have, and needs
+//! no changes the day real spans land.
+
+use crate::ast::Span;
+use crate::check::{SemanticError, TypeName};
+use crate::compiler::CompileError;
+#[cfg(test)]
+use crate::Compile as _;
+use std::fmt::Write as _;
+
+/// A single reportable problem: the `span` it occurred at, a one-line
+/// `message` describing what went wrong, and an optional `help` suggesting
+/// a fix. Built from a [`CompileError`] or [`SemanticError`] via `From`, or
+/// directly for callers that have their own source of diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    /// Attaches a suggested fix, rendered as a trailing `= help: ...` line.
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(err: &CompileError) -> Self {
+        Diagnostic::new(err.span, err.message.clone())
+    }
+}
+
+impl From<&SemanticError> for Diagnostic {
+    fn from(err: &SemanticError) -> Self {
+        let diag = Diagnostic::new(err.location(), err.to_string());
+        match err {
+            // A quoted string on one side and a duration/time literal on
+            // the other is the same "forgot to drop the quotes" mistake
+            // `SemanticError::WaitRequiresDuration` already catches for
+            // `wait`'s operand — just on the other side of a comparison.
+            SemanticError::IncomparableTypes {
+                lhs: TypeName::String,
+                rhs: TypeName::Duration | TypeName::Time,
+                ..
+            }
+            | SemanticError::IncomparableTypes {
+                lhs: TypeName::Duration | TypeName::Time,
+                rhs: TypeName::String,
+                ..
+            } => diag.with_help(
+                "a quoted string never equals a duration/time literal; drop the quotes \
+                 if you meant to compare against it directly",
+            ),
+            _ => diag,
+        }
+    }
+}
+
+/// The 1-based `(line, column)` of byte offset `offset` into `source`.
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let mut line = 1;
+    let mut col = 1;
+    for ch in source[..offset].chars() {
+        if ch == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    (line, col)
+}
+
+/// The 1-based `line`'th line of `source`, or `""` past the end.
+fn nth_line(source: &str, line: usize) -> &str {
+    source.lines().nth(line - 1).unwrap_or("")
+}
+
+/// Renders `diagnostics` as caret-underlined snippets of `source`, one
+/// report per diagnostic, e.g.:
+///
+/// ```text
+/// error: undefined identifier 'x'
+///   --> line 2, column 7
+///    |
+///  2 | print x;
+///    |       ^
+/// ```
+///
+/// A diagnostic whose `span` is `Span::default()` — the placeholder every
+/// node carries until the parser starts stamping real positions (see
+/// [`Span`]'s doc comment) — gets just the message line instead: claiming
+/// "line 1, column 1" for a position that was never actually recorded
+/// would be worse than not printing one.
+pub fn render(source: &str, diagnostics: &[Diagnostic]) -> String {
+    let mut out = String::new();
+    for diag in diagnostics {
+        if diag.span == Span::default() {
+            let _ = writeln!(out, "error: {}", diag.message);
+            if let Some(help) = &diag.help {
+                let _ = writeln!(out, "   = help: {help}");
+            }
+            out.push('\n');
+            continue;
+        }
+        let (line, col) = line_col(source, diag.span.start);
+        let text = nth_line(source, line);
+        let width = diag.span.end.saturating_sub(diag.span.start).max(1);
+        let _ = writeln!(out, "error: {}", diag.message);
+        let _ = writeln!(out, "  --> line {line}, column {col}");
+        let _ = writeln!(out, "   |");
+        let _ = writeln!(out, "{line:>3} | {text}");
+        let _ = writeln!(
+            out,
+            "   | {}{}",
+            " ".repeat(col.saturating_sub(1)),
+            "^".repeat(width)
+        );
+        if let Some(help) = &diag.help {
+            let _ = writeln!(out, "   = help: {help}");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_points_at_the_span() {
+        let source = "let x = 1;\nprint y;\n";
+        let diag = Diagnostic::new(Span::new(17, 18), "undefined identifier 'y'")
+            .with_help("did you mean 'x'?");
+        let rendered = render(source, &[diag]);
+        assert_eq!(
+            "error: undefined identifier 'y'\n\
+             \u{20}\x20--> line 2, column 7\n\
+             \u{20}\x20\x20|\n\
+             \x20\x202 | print y;\n\
+             \x20\x20\x20| \x20\x20\x20\x20\x20\x20^\n\
+             \x20\x20\x20= help: did you mean 'x'?\n\n",
+            rendered
+        );
+    }
+
+    #[test]
+    fn test_render_falls_back_to_message_for_default_span() {
+        // Every node in this tree carries `Span::default()` until the
+        // parser starts stamping real positions; rendering a line/column
+        // for it would claim a position that was never actually recorded.
+        let diag = Diagnostic::new(Span::default(), "something went wrong");
+        let rendered = render("print 1;", &[diag]);
+        assert_eq!("error: something went wrong\n\n", rendered);
+    }
+
+    #[test]
+    fn test_compile_error_to_diagnostic() {
+        // Same "reference an undefined identifier" case
+        // `compiler::tests::test_undefined_identifier_reports_compile_error`
+        // uses to get a real `CompileError` — there's no public constructor,
+        // so this goes through `Interpreter::from_ast` rather than building
+        // one by hand.
+        let ast = crate::ast::Stmt::spanned(crate::ast::StmtKind::Print(crate::ast::Expr::new(
+            crate::ast::ExprKind::Ident("undefined".to_string()),
+            Span::new(6, 15),
+        )));
+        let err = crate::compiler::Interpreter::from_ast(ast).unwrap_err();
+        let diag: Diagnostic = (&err).into();
+        assert_eq!(Span::new(6, 15), diag.span);
+        assert!(diag.message.contains("undefined"));
+        assert_eq!(None, diag.help);
+    }
+
+    #[test]
+    fn test_semantic_error_to_diagnostic() {
+        let err = SemanticError::UndefinedIdentifier {
+            name: "y".to_string(),
+            location: Span::new(17, 18),
+        };
+        let diag: Diagnostic = (&err).into();
+        assert_eq!(Span::new(17, 18), diag.span);
+        assert_eq!("undefined identifier `y`", diag.message);
+        assert_eq!(None, diag.help);
+    }
+
+    #[test]
+    fn test_incomparable_types_gets_a_dropped_quotes_help() {
+        let err = SemanticError::IncomparableTypes {
+            lhs: TypeName::String,
+            rhs: TypeName::Duration,
+            location: Span::new(0, 4),
+        };
+        let diag: Diagnostic = (&err).into();
+        assert!(diag.help.is_some());
+    }
+}
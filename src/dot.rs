@@ -0,0 +1,133 @@
+//! Renders a compiled [`Code`] as a Graphviz `digraph` of its instruction
+//! sequence, so a program's control flow — in particular where and how
+//! `Instruction::Spawn` fans a single thread out into several concurrent
+//! ones — can be inspected visually instead of read off `Code::instructions`
+//! by hand. Mirrors [`Code::to_bytes`]/[`Code::write_to`]'s in-memory/
+//! on-disk split: [`to_dot`] for an in-memory string, [`to_writer`] for
+//! streaming straight to a file or other sink (e.g. piping into `dot -Tsvg`).
+
+use crate::compiler::{Code, Instruction};
+use std::io::{self, Write};
+
+/// Renders `code` as a Graphviz `digraph` source string. See the module
+/// docs for what the graph contains.
+pub fn to_dot(code: &Code) -> String {
+    let mut out = Vec::new();
+    // `Vec<u8>` is `Write`; this can't actually fail, hence the `unwrap`.
+    to_writer(code, &mut out).unwrap();
+    String::from_utf8(out).expect("dot output is always valid UTF-8")
+}
+
+/// Same as [`to_dot`], but writes straight to `writer` instead of building
+/// up a `String` first.
+pub fn to_writer(code: &Code, mut writer: impl Write) -> io::Result<()> {
+    writeln!(writer, "digraph Code {{")?;
+    writeln!(writer, "    rankdir=TB;")?;
+    for (i, instruction) in code.instructions.iter().enumerate() {
+        let label = escape(&instruction_label(code, i, instruction));
+        if matches!(instruction, Instruction::Term) {
+            writeln!(writer, "    i{i} [shape=doubleoctagon, label=\"{label}\"];")?;
+        } else {
+            writeln!(writer, "    i{i} [shape=box, label=\"{label}\"];")?;
+        }
+    }
+    for (i, instruction) in code.instructions.iter().enumerate() {
+        if let Instruction::Spawn(target) = instruction {
+            // `Spawn(target)`'s operand is where the *parent* thread resumes
+            // once it's done spawning (see `ThreadContext::step`'s `Instruction::
+            // Spawn` arm: `self.ip = ip` there); the spawned child is the
+            // one that falls through sequentially to `i + 1`. So the
+            // fall-through edge, not the jump-target one, is the fan-out
+            // this graph exists to show.
+            if i + 1 < code.instructions.len() {
+                writeln!(
+                    writer,
+                    "    i{i} -> i{} [label=\"spawn\", style=dashed];",
+                    i + 1
+                )?;
+            }
+            writeln!(writer, "    i{i} -> i{target};")?;
+            continue;
+        }
+        // `Term` ends its thread; nothing falls through from it.
+        if !matches!(instruction, Instruction::Term) && i + 1 < code.instructions.len() {
+            writeln!(writer, "    i{i} -> i{};", i + 1)?;
+        }
+    }
+    writeln!(writer, "}}")
+}
+
+/// `"<ip>: <opcode>(<operand>)"`, with a `Constant`'s operand resolved to
+/// the actual [`crate::compiler::Value`] it indexes into `code.constants`
+/// instead of just the bare index, since the index alone tells a reader
+/// nothing about what the instruction actually pushes.
+fn instruction_label(code: &Code, ip: usize, instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::Constant(index) => match code.constants.get(*index) {
+            Some(value) => format!("{ip}: Constant({index}) = {value:?}"),
+            None => format!("{ip}: Constant({index})"),
+        },
+        other => format!("{ip}: {other:?}"),
+    }
+}
+
+/// Escapes `"` and `\` so `label` is safe to embed in a DOT quoted string.
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{Code, Instruction, Value};
+
+    #[test]
+    fn test_to_dot_sequential_edges() {
+        let code = Code {
+            instructions: vec![
+                Instruction::Constant(0),
+                Instruction::Print,
+                Instruction::Term,
+            ],
+            constants: vec![Value::Integer(1)],
+            ..Default::default()
+        };
+        let dot = to_dot(&code);
+        assert!(dot.contains("i0 [shape=box, label=\"0: Constant(0) = Integer(1)\"];"));
+        assert!(dot.contains("i1 [shape=box, label=\"1: Print\"];"));
+        assert!(dot.contains("i2 [shape=doubleoctagon, label=\"2: Term\"];"));
+        assert!(dot.contains("i0 -> i1;"));
+        assert!(dot.contains("i1 -> i2;"));
+        // `Term` is a terminal: nothing falls through from it.
+        assert!(!dot.contains("i2 -> i3;"));
+    }
+
+    #[test]
+    fn test_to_dot_spawn_edge() {
+        let code = Code {
+            instructions: vec![
+                Instruction::Spawn(2),
+                Instruction::Term,
+                Instruction::Print,
+                Instruction::Term,
+            ],
+            ..Default::default()
+        };
+        let dot = to_dot(&code);
+        // The spawned child is the fall-through instruction, not the jump
+        // target: `Spawn`'s operand is where the *parent* resumes.
+        assert!(dot.contains("i0 -> i1 [label=\"spawn\", style=dashed];"));
+        assert!(dot.contains("i0 -> i2;"));
+    }
+
+    #[test]
+    fn test_to_dot_escapes_string_constants() {
+        let code = Code {
+            instructions: vec![Instruction::Constant(0)],
+            constants: vec![Value::Str("say \"hi\"".to_string())],
+            ..Default::default()
+        };
+        let dot = to_dot(&code);
+        assert!(dot.contains(r#"Str(\"say \\\"hi\\\"\")"#));
+    }
+}
@@ -0,0 +1,61 @@
+//! Named, id-stable fault kinds that a `try`/`on error` handler can match on.
+//!
+//! Runtime failures normally arrive as an opaque [`anyhow::Error`] (a broker
+//! disconnect, a missing path, a bad payload, ...). `Fault` gives a small,
+//! fixed set of those failures a name that survives compilation, so
+//! `compiler::Interpreter` can bind it to an identifier in a handler body and
+//! `vm::VM` can raise the same identity a user's program matched against.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Fault {
+    /// The MQTT broker connection dropped or could not be reached.
+    MqttDisconnected,
+    /// `get`/`watch` was asked for a path the engine has no value for.
+    PathNotFound,
+    /// A value didn't have the shape an instruction expected (e.g. a
+    /// malformed payload that failed to parse into a `Value`).
+    TypeError,
+    /// A `wait`/`at` deadline elapsed before the awaited condition resolved.
+    TimeoutElapsed,
+    /// Anything that doesn't match one of the named kinds above.
+    Other,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::MqttDisconnected => f.write_str("MqttDisconnected"),
+            Fault::PathNotFound => f.write_str("PathNotFound"),
+            Fault::TypeError => f.write_str("TypeError"),
+            Fault::TimeoutElapsed => f.write_str("TimeoutElapsed"),
+            Fault::Other => f.write_str("Other"),
+        }
+    }
+}
+
+impl Fault {
+    /// Best-effort classification of an arbitrary runtime error into a named
+    /// fault kind. Engines today just raise plain `anyhow::Error`s, so this
+    /// matches on the message rather than a typed source; it only needs to
+    /// be good enough to steer a handler, not perfectly precise.
+    pub fn classify(err: &anyhow::Error) -> Fault {
+        let msg = err.to_string().to_lowercase();
+        if msg.contains("disconnect") || msg.contains("mqtt") || msg.contains("broker") {
+            Fault::MqttDisconnected
+        } else if msg.contains("not found") || msg.contains("no value") {
+            Fault::PathNotFound
+        } else if msg.contains("elapsed") || msg.contains("timed out") || msg.contains("timeout") {
+            Fault::TimeoutElapsed
+        } else if msg.contains("must be")
+            || msg.contains("type")
+            || msg.contains("utf-8")
+            || msg.contains("parse")
+        {
+            Fault::TypeError
+        } else {
+            Fault::Other
+        }
+    }
+}
@@ -1,19 +1,62 @@
 pub mod ast;
+pub mod check;
 pub mod compiler;
+pub mod config;
+pub mod diagnostics;
+pub mod dot;
+pub mod fault;
 pub mod mqtt_engine;
+pub mod nats_engine;
 pub mod parser;
+pub mod spawner;
+pub mod sun;
+pub mod testkit;
 pub mod vm;
-//pub mod sun;
 
 pub type Result<T> = anyhow::Result<T>;
 
 pub trait Compile {
     type Output;
+    type Error;
 
-    fn from_ast(ast: ast::Stmt) -> Self::Output;
+    fn from_ast(ast: ast::Stmt) -> std::result::Result<Self::Output, Self::Error>;
 
-    fn from_source(source: &str) -> Result<Self::Output> {
+    fn from_source(source: &str) -> Result<Self::Output>
+    where
+        Self::Error: std::error::Error + Send + Sync + 'static,
+    {
         let ast = parser::parse(source)?;
-        Ok(Self::from_ast(ast))
+        if let Err(errors) = check::check(&ast) {
+            let diagnostics: Vec<diagnostics::Diagnostic> =
+                errors.iter().map(diagnostics::Diagnostic::from).collect();
+            return Err(anyhow::anyhow!(diagnostics::render(source, &diagnostics)));
+        }
+        Self::from_ast(ast).map_err(anyhow::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Interpreter;
+
+    #[test]
+    fn test_from_source_rejects_semantically_invalid_program() {
+        // `wait` on a string operand compiles fine today if semantic
+        // checking is skipped, then fails at runtime with a type mismatch
+        // in `ThreadContext::step`.
+        // `from_source` now runs `check::check` before codegen so this
+        // fails here instead, with a message pointing at the mistake.
+        let err = Interpreter::from_source(r#"wait "on" print "x";"#).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("wait"),
+            "expected a message about `wait`'s operand, got: {message}"
+        );
+    }
+
+    #[test]
+    fn test_from_source_accepts_semantically_valid_program() {
+        assert!(Interpreter::from_source(r#"wait 1s print "x";"#).is_ok());
     }
 }
@@ -1,20 +1,78 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::{
     select,
     sync::{mpsc, oneshot},
     task::JoinHandle,
+    time,
 };
 
 use crate::vm::Engine;
 
 use mqtt_async_client::client::{Client, Publish, QoS, ReadResult, Subscribe, SubscribeTopic};
 
+/// How often [`MQTTEngine::supervise`] checks that data has come through
+/// recently, forcing a reconnect if the connection has gone quiet for a
+/// whole interval without erroring on its own.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Backoff delay doubles each attempt from [`RetryPolicy::base_delay`],
+/// jittered by up to ±50%, capped at [`RetryPolicy::max_delay`].
+const BACKOFF_FACTOR: f64 = 2.0;
+
+/// How hard [`MQTTEngine`] fights a flaky broker before giving up: the
+/// connection supervisor backs off between reconnect attempts per
+/// `base_delay`/`max_delay` (and keeps retrying forever in the background,
+/// since a long-running `dan` program should survive a broker restart), and
+/// `get`/`set` each retry an individual call up to `max_attempts` times
+/// (same backoff curve) before surfacing an error to the caller instead of
+/// hanging indefinitely on a connection that may never come back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(300),
+            max_attempts: 5,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MQTTEngine {
     requests_tx: mpsc::Sender<Request>,
     join_handle: JoinHandle<Result<()>>,
+    location: (f64, f64),
+    timezone: chrono_tz::Tz,
+    /// Whether the broker connection is currently up, kept in sync by the
+    /// supervisor task so `get`/`set` can fail fast with a classifiable
+    /// error instead of hanging while a reconnect is in flight.
+    connected: Arc<AtomicBool>,
+    retry_policy: RetryPolicy,
+}
+
+/// A last-will-and-testament message: the broker publishes this itself if
+/// the connection drops without a clean [`MQTTEngine::shutdown`], so other
+/// automations can react to this runtime going offline unexpectedly.
+#[derive(Debug, Clone)]
+pub struct LastWill {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
 }
 
 #[derive(Debug)]
@@ -32,30 +90,216 @@ struct Get {
 enum SelectResult {
     Request(Option<Request>),
     Data(ReadResult),
+    HealthCheck,
 }
 
-impl MQTTEngine {
-    pub fn new(url: &str) -> Result<Arc<Self>> {
-        // Create a client & define connect options
-        let cli = Client::builder().set_url_string(url)?.build()?;
+/// Whether `topic` is matched by the MQTT topic filter `filter` (`+`/`#`
+/// wildcards), per the standard MQTT topic-matching rules: filter and topic
+/// are split on `/` and walked level by level, where `+` matches exactly one
+/// topic level, a trailing `#` matches the rest of the topic (zero or more
+/// levels), and any other level must match literally. A `#` or `+` in the
+/// first level never matches a topic whose first level starts with `$`
+/// (e.g. `$SYS/...`); that level must be spelled out literally in
+/// `filter`, same as real brokers.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    let mut first = true;
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), topic_level) => {
+                return !(first && topic_level.is_some_and(|l| l.starts_with('$')));
+            }
+            (Some("+"), Some(topic_level)) => {
+                if first && topic_level.starts_with('$') {
+                    return false;
+                }
+            }
+            (Some("+"), None) => return false,
+            (Some(f), Some(t)) => {
+                if f != t {
+                    return false;
+                }
+            }
+            (Some(_), None) => return false,
+            (None, Some(_)) => return false,
+            (None, None) => return true,
+        }
+        first = false;
+    }
+}
+
+/// A capped, jittered exponential backoff delay for the `attempt`th retry
+/// (0-indexed) under `policy`: doubles from `base_delay` each attempt,
+/// capped at `max_delay`, jittered by up to ±50%.
+fn backoff_delay(attempt: u32, policy: &RetryPolicy) -> Duration {
+    let base = policy.base_delay.as_secs_f64() * BACKOFF_FACTOR.powi(attempt as i32);
+    let capped = base.min(policy.max_delay.as_secs_f64());
+    // No `rand` dependency anywhere in this tree; jitter off of the
+    // sub-second portion of the wall clock instead of adding one.
+    let jitter_unit = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => f64::from(d.subsec_nanos()) / 1_000_000_000_f64,
+        Err(_) => 0.5,
+    };
+    Duration::from_secs_f64(capped * (0.5 + jitter_unit)) // +/-50%
+}
 
+impl MQTTEngine {
+    /// `location` is the installation's (latitude, longitude) in degrees
+    /// and `timezone` its IANA timezone, used together to resolve solar
+    /// `at` events (`#sunrise`, `#dusk`, etc.) and wall-clock literals.
+    /// `lwt`, if given, is registered with the broker on every (re)connect
+    /// so it fires if this runtime disappears without a clean
+    /// [`Self::shutdown`]. `retry_policy` governs both the connection
+    /// supervisor's reconnect backoff and how hard `get`/`set` each retry a
+    /// single call before giving up (see [`RetryPolicy`]).
+    pub fn new(
+        url: &str,
+        location: (f64, f64),
+        timezone: chrono_tz::Tz,
+        lwt: Option<LastWill>,
+        retry_policy: RetryPolicy,
+    ) -> Result<Arc<Self>> {
+        let url = url.to_string();
         let (requests_tx, requests_rx) = mpsc::channel(100);
-        let join_handle = tokio::spawn(async move { Self::run(cli, requests_rx).await });
+        let connected = Arc::new(AtomicBool::new(false));
+        let join_handle = {
+            let connected = connected.clone();
+            tokio::spawn(async move {
+                Self::supervise(url, lwt, requests_rx, connected, retry_policy).await
+            })
+        };
         Ok(Arc::new(Self {
             requests_tx,
             join_handle,
+            location,
+            timezone,
+            connected,
+            retry_policy,
         }))
     }
-    async fn run(mut cli: Client, mut requests_rx: mpsc::Receiver<Request>) -> Result<()> {
-        cli.connect().await?;
+
+    /// Whether the broker connection is currently up. While this is false a
+    /// reconnect is either being attempted or backed off from; callers
+    /// should expect `get`/`set` to fail with a fault the VM can classify as
+    /// [`crate::fault::Fault::MqttDisconnected`] until it flips back to true.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Retries `op` (a single attempt at a `get`/`set`) up to
+    /// `self.retry_policy.max_attempts` times, backing off between attempts
+    /// the same way the connection supervisor does between reconnects. This
+    /// is what keeps a transient broker blip from killing the calling `dan`
+    /// thread outright: the caller only sees an error once the retry budget
+    /// is exhausted.
+    async fn with_retry<T, F, Fut>(&self, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut attempt = 0_u32;
+        loop {
+            if self.is_connected() {
+                match op().await {
+                    Ok(value) => return Ok(value),
+                    Err(err) if attempt + 1 >= self.retry_policy.max_attempts => return Err(err),
+                    Err(err) => {
+                        log::warn!("mqtt operation failed, retrying: {}", err);
+                    }
+                }
+            } else if attempt + 1 >= self.retry_policy.max_attempts {
+                return Err(anyhow!("mqtt broker disconnected; reconnect in progress"));
+            }
+            time::sleep(backoff_delay(attempt, &self.retry_policy)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Owns the connection for the engine's lifetime. Connects, serves
+    /// requests until the connection drops or goes quiet, then reconnects
+    /// with backoff and replays every subscription that was active before
+    /// the drop, so `when`/`set` on existing paths resume transparently.
+    /// Only returns once `requests_rx` closes, i.e. [`Self::shutdown`] ran.
+    async fn supervise(
+        url: String,
+        lwt: Option<LastWill>,
+        mut requests_rx: mpsc::Receiver<Request>,
+        connected: Arc<AtomicBool>,
+        retry_policy: RetryPolicy,
+    ) -> Result<()> {
+        // Tracked across reconnects (not reset per attempt) so a dropped
+        // connection comes back subscribed to everything it was before.
+        let mut subscriptions: HashSet<String> = HashSet::new();
+        let mut attempt = 0_u32;
+        loop {
+            let mut builder = Client::builder();
+            builder.set_url_string(&url)?;
+            if let Some(lwt) = &lwt {
+                let mut msg = Publish::new(lwt.topic.clone(), lwt.payload.clone());
+                msg.set_qos(lwt.qos);
+                msg.set_retain(lwt.retain);
+                builder.set_last_will_message(msg);
+            }
+            let mut cli = builder.build()?;
+            if let Err(err) = cli.connect().await {
+                log::warn!("mqtt connect failed, backing off: {}", err);
+                if !Self::backoff(&mut attempt, &retry_policy, &mut requests_rx).await {
+                    return Ok(());
+                }
+                continue;
+            }
+            if !subscriptions.is_empty() {
+                let topics = subscriptions
+                    .iter()
+                    .map(|topic_path| SubscribeTopic {
+                        topic_path: topic_path.clone(),
+                        qos: QoS::AtLeastOnce,
+                    })
+                    .collect();
+                if let Err(err) = cli.subscribe(Subscribe::new(topics)).await {
+                    log::warn!("mqtt re-subscribe failed, backing off: {}", err);
+                    if !Self::backoff(&mut attempt, &retry_policy, &mut requests_rx).await {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            }
+            connected.store(true, Ordering::SeqCst);
+            attempt = 0;
+            let result = Self::serve(&mut cli, &mut requests_rx, &mut subscriptions).await;
+            connected.store(false, Ordering::SeqCst);
+            let _ = cli.disconnect().await;
+            match result {
+                // `requests_rx` closed: `shutdown` ran, stop for good.
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    log::warn!("mqtt connection lost, reconnecting: {}", err);
+                    if !Self::backoff(&mut attempt, &retry_policy, &mut requests_rx).await {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Serves requests and incoming data over an already-established
+    /// connection until it fails, goes quiet for [`HEALTH_CHECK_INTERVAL`],
+    /// or `requests_rx` closes (returned as `Ok(())`, a clean shutdown
+    /// rather than a connection failure).
+    async fn serve(
+        cli: &mut Client,
+        requests_rx: &mut mpsc::Receiver<Request>,
+        subscriptions: &mut HashSet<String>,
+    ) -> Result<()> {
         let mut watches: Vec<Get> = Vec::new();
-        // Deduplicate subscriptions so we do not get busy loops getting messages of after
-        // re-subscribing.
-        let mut subscriptions: HashSet<String, _> = HashSet::new();
+        let mut health_check = time::interval(HEALTH_CHECK_INTERVAL);
+        health_check.tick().await; // first tick fires immediately
         loop {
             let s = select! {
                 req = requests_rx.recv() =>  SelectResult::Request(req),
                 data = cli.read_subscriptions() =>  SelectResult::Data(data?),
+                _ = health_check.tick() => SelectResult::HealthCheck,
             };
             match s {
                 SelectResult::Request(req) => match req {
@@ -71,9 +315,10 @@ impl MQTTEngine {
                         }
                         subscriptions.extend(topic_paths.into_iter());
                     }
-                    None => break,
+                    None => return Ok(()),
                 },
                 SelectResult::Data(data) => {
+                    health_check.reset();
                     log::debug!(
                         "data receieved for topic {} {}",
                         data.topic(),
@@ -81,7 +326,7 @@ impl MQTTEngine {
                     );
                     let mut i = 0_usize;
                     while i < watches.len() {
-                        if data.topic() == watches[i].path {
+                        if topic_matches(&watches[i].path, data.topic()) {
                             let w = watches.remove(i);
                             w.tx.send(data.payload().to_vec()).unwrap();
                             continue;
@@ -89,13 +334,44 @@ impl MQTTEngine {
                         i += 1;
                     }
                 }
+                SelectResult::HealthCheck => {
+                    return Err(anyhow!(
+                        "mqtt broker disconnected: no activity for {:?}",
+                        HEALTH_CHECK_INTERVAL
+                    ));
+                }
             }
         }
-        let r = cli.disconnect().await;
-        Ok(r?)
     }
+
+    /// Sleeps for a capped, jittered exponential backoff delay (per
+    /// `policy`) and bumps `attempt`. Returns `false` without sleeping if
+    /// `requests_rx` closes first, so a reconnect loop can stop promptly
+    /// once `shutdown` runs.
+    async fn backoff(
+        attempt: &mut u32,
+        policy: &RetryPolicy,
+        requests_rx: &mut mpsc::Receiver<Request>,
+    ) -> bool {
+        let jittered = backoff_delay(*attempt, policy);
+        *attempt += 1;
+        let sleep = time::sleep(jittered);
+        tokio::pin!(sleep);
+        loop {
+            select! {
+                _ = &mut sleep => return true,
+                // A request sent right as the connection dropped, before
+                // `connected` flipped false, lands here instead of at an
+                // `is_connected` check — drop it and keep backing off.
+                req = requests_rx.recv() => if req.is_none() {
+                    return false;
+                },
+            }
+        }
+    }
+
     pub async fn shutdown(self) -> Result<()> {
-        // Explicitly drop request_tx so that the run loop
+        // Explicitly drop request_tx so that the supervisor loop
         // knows its done
         drop(self.requests_tx);
         self.join_handle.await??;
@@ -105,26 +381,108 @@ impl MQTTEngine {
 
 #[async_trait]
 impl Engine for Arc<MQTTEngine> {
+    /// Subscribing to `path` makes the broker immediately replay its
+    /// retained message for that topic (if any), which lands in `serve`'s
+    /// `SelectResult::Data` arm the same way a live update would. Since
+    /// `set` always publishes retained (see below), that replay is the
+    /// topic's current state, so this resolves right away on a fresh
+    /// subscription instead of hanging until something new is published.
+    ///
+    /// `Engine::watch`'s default impl polls this in a loop, one `get` per
+    /// stream item: the first poll subscribes and gets the retained reply
+    /// above; later polls skip the (now-deduped) subscribe and each just
+    /// waits for the next live publish. Either way, each call only ever
+    /// registers one outstanding `Get` for `path`, and `serve` resolves every
+    /// outstanding `Get` whose filter matches a topic with the first message
+    /// that arrives for it — so a retained replay and a live update are handled
+    /// identically without needing to tell them apart.
+    ///
+    /// Retries through [`MQTTEngine::with_retry`], so a transient send
+    /// failure while a reconnect is in flight doesn't immediately surface
+    /// to the `dan` thread that's waiting on it.
     async fn get(&self, path: &str) -> Result<Vec<u8>> {
-        let (tx, rx) = oneshot::channel();
-        self.requests_tx
-            .send(Request::Get(Get {
-                path: path.to_string(),
-                tx,
-            }))
-            .await?;
-        // Subscribe after sending get so we are listening before we recieve the response
-        let s = Subscribe::new(vec![SubscribeTopic {
-            topic_path: path.to_string(),
-            qos: QoS::AtLeastOnce,
-        }]);
-        self.requests_tx.send(Request::Subscribe(s)).await?;
-        Ok(rx.await?)
+        self.with_retry(|| async {
+            let (tx, rx) = oneshot::channel();
+            self.requests_tx
+                .send(Request::Get(Get {
+                    path: path.to_string(),
+                    tx,
+                }))
+                .await?;
+            // Subscribe after sending get so we are listening before we recieve the response
+            let s = Subscribe::new(vec![SubscribeTopic {
+                topic_path: path.to_string(),
+                qos: QoS::AtLeastOnce,
+            }]);
+            self.requests_tx.send(Request::Subscribe(s)).await?;
+            Ok(rx.await?)
+        })
+        .await
     }
 
+    /// Publishes retained, so the broker keeps serving this as `path`'s
+    /// current value to anything that subscribes later (see `get`). Retries
+    /// through [`MQTTEngine::with_retry`] the same as `get`.
     async fn set(&self, path: &str, value: Vec<u8>) -> Result<()> {
-        let msg = Publish::new(path.to_string(), value);
-        self.requests_tx.send(Request::Publish(msg)).await?;
-        Ok(())
+        self.with_retry(|| async {
+            let mut msg = Publish::new(path.to_string(), value.clone());
+            msg.set_retain(true);
+            self.requests_tx.send(Request::Publish(msg)).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn location(&self) -> (f64, f64) {
+        self.location
+    }
+
+    fn timezone(&self) -> chrono_tz::Tz {
+        self.timezone
+    }
+
+    /// Whether the broker connection is currently up (see
+    /// [`MQTTEngine::is_connected`]).
+    async fn health(&self) -> bool {
+        self.is_connected()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::topic_matches;
+
+    #[test]
+    fn test_topic_matches_literal() {
+        assert!(topic_matches("foo/bar", "foo/bar"));
+        assert!(!topic_matches("foo/bar", "foo/baz"));
+        assert!(!topic_matches("foo/bar", "foo/bar/baz"));
+    }
+
+    #[test]
+    fn test_topic_matches_plus() {
+        assert!(topic_matches("foo/+/status", "foo/device1/status"));
+        assert!(topic_matches("foo/+/status", "foo/device2/status"));
+        assert!(!topic_matches("foo/+/status", "foo/status"));
+        assert!(!topic_matches("foo/+/status", "foo/a/b/status"));
+        assert!(topic_matches("+", "foo"));
+    }
+
+    #[test]
+    fn test_topic_matches_hash() {
+        assert!(topic_matches("foo/#", "foo"));
+        assert!(topic_matches("foo/#", "foo/bar"));
+        assert!(topic_matches("foo/#", "foo/bar/baz"));
+        assert!(!topic_matches("foo/#", "bar"));
+    }
+
+    #[test]
+    fn test_topic_matches_dollar_exclusion() {
+        // A bare `#` or leading `+` must not match a topic whose first
+        // level starts with `$`, same as a real broker's `$SYS` topics.
+        assert!(!topic_matches("#", "$SYS/broker/uptime"));
+        assert!(!topic_matches("+/uptime", "$SYS/uptime"));
+        // But a literal `$`-prefixed filter level still matches normally.
+        assert!(topic_matches("$SYS/#", "$SYS/broker/uptime"));
     }
 }
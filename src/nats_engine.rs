@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::stream::{BoxStream, SelectAll, StreamExt};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time,
+    time::Duration,
+};
+
+use crate::vm::Engine;
+
+use async_nats::{Client, Message};
+
+/// Delay between reconnect attempts. NATS subjects have no retained-message
+/// concept to replay on resubscribe (unlike [`crate::mqtt_engine::MQTTEngine`]),
+/// so a dropped connection just re-subscribes and waits for the next publish
+/// rather than needing MQTT's jittered backoff/health-check machinery.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// An [`Engine`] backed by a NATS server instead of an MQTT broker, using
+/// NATS subjects as the path namespace. Structured the same way as
+/// [`crate::mqtt_engine::MQTTEngine`]: a spawned `supervise` loop owns the
+/// connection and serves requests sent over an `mpsc` channel, with a
+/// `oneshot` resolved per outstanding `get`.
+#[derive(Debug)]
+pub struct NATSEngine {
+    requests_tx: mpsc::Sender<Request>,
+    join_handle: JoinHandle<Result<()>>,
+    /// Whether the NATS connection is currently up, kept in sync by the
+    /// supervisor task so `get`/`set` can fail fast with a classifiable
+    /// error instead of hanging while a reconnect is in flight.
+    connected: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+enum Request {
+    Publish(String, Vec<u8>),
+    Subscribe(String),
+    Get(Get),
+}
+#[derive(Debug)]
+struct Get {
+    path: String,
+    tx: oneshot::Sender<Vec<u8>>,
+}
+
+enum SelectResult {
+    Request(Option<Request>),
+    Data(Message),
+}
+
+impl NATSEngine {
+    pub fn new(url: &str) -> Result<Arc<Self>> {
+        let url = url.to_string();
+        let (requests_tx, requests_rx) = mpsc::channel(100);
+        let connected = Arc::new(AtomicBool::new(false));
+        let join_handle = {
+            let connected = connected.clone();
+            tokio::spawn(async move { Self::supervise(url, requests_rx, connected).await })
+        };
+        Ok(Arc::new(Self {
+            requests_tx,
+            join_handle,
+            connected,
+        }))
+    }
+
+    /// Whether the NATS connection is currently up. While this is false a
+    /// reconnect is either being attempted or backed off from; callers
+    /// should expect `get`/`set` to fail until it flips back to true.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Owns the connection for the engine's lifetime. Connects, serves
+    /// requests until the connection drops, then reconnects and replays
+    /// every subscription that was active before the drop, so `when`/`set`
+    /// on existing paths resume transparently. Only returns once
+    /// `requests_rx` closes, i.e. [`Self::shutdown`] ran.
+    async fn supervise(
+        url: String,
+        mut requests_rx: mpsc::Receiver<Request>,
+        connected: Arc<AtomicBool>,
+    ) -> Result<()> {
+        // Tracked across reconnects (not reset per attempt) so a dropped
+        // connection comes back subscribed to everything it was before.
+        let mut subjects: Vec<String> = Vec::new();
+        loop {
+            let client = match async_nats::connect(&url).await {
+                Ok(client) => client,
+                Err(err) => {
+                    log::warn!("nats connect failed, retrying: {}", err);
+                    if !Self::sleep_or_stop(&mut requests_rx).await {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+            let mut subs: SelectAll<BoxStream<'static, Message>> = SelectAll::new();
+            let mut resubscribe_failed = false;
+            for subject in &subjects {
+                match client.subscribe(subject.clone()).await {
+                    Ok(sub) => subs.push(Box::pin(sub)),
+                    Err(err) => {
+                        log::warn!("nats re-subscribe to {} failed: {}", subject, err);
+                        resubscribe_failed = true;
+                        break;
+                    }
+                }
+            }
+            if resubscribe_failed {
+                if !Self::sleep_or_stop(&mut requests_rx).await {
+                    return Ok(());
+                }
+                continue;
+            }
+            connected.store(true, Ordering::SeqCst);
+            let result = Self::serve(&client, &mut requests_rx, &mut subjects, &mut subs).await;
+            connected.store(false, Ordering::SeqCst);
+            match result {
+                // `requests_rx` closed: `shutdown` ran, stop for good.
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    log::warn!("nats connection lost, reconnecting: {}", err);
+                    if !Self::sleep_or_stop(&mut requests_rx).await {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Serves requests and incoming data over an already-established
+    /// connection until `requests_rx` closes (returned as `Ok(())`, a clean
+    /// shutdown rather than a connection failure).
+    async fn serve(
+        client: &Client,
+        requests_rx: &mut mpsc::Receiver<Request>,
+        subjects: &mut Vec<String>,
+        subs: &mut SelectAll<BoxStream<'static, Message>>,
+    ) -> Result<()> {
+        let mut watches: Vec<Get> = Vec::new();
+        loop {
+            let s = select! {
+                req = requests_rx.recv() => SelectResult::Request(req),
+                data = subs.next(), if !subs.is_empty() => match data {
+                    Some(msg) => SelectResult::Data(msg),
+                    None => return Err(anyhow!("nats subscription stream ended")),
+                },
+            };
+            match s {
+                SelectResult::Request(req) => match req {
+                    Some(Request::Get(watch)) => watches.push(watch),
+                    Some(Request::Publish(subject, payload)) => {
+                        client.publish(subject, payload.into()).await?;
+                    }
+                    Some(Request::Subscribe(subject)) => {
+                        if !subjects.iter().any(|s| s == &subject) {
+                            let sub = client.subscribe(subject.clone()).await?;
+                            subs.push(Box::pin(sub));
+                            subjects.push(subject);
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                SelectResult::Data(data) => {
+                    log::debug!(
+                        "data received for subject {} {}",
+                        data.subject.as_str(),
+                        String::from_utf8_lossy(&data.payload),
+                    );
+                    let mut i = 0_usize;
+                    while i < watches.len() {
+                        if data.subject.as_str() == watches[i].path {
+                            let w = watches.remove(i);
+                            let _ = w.tx.send(data.payload.to_vec());
+                            continue;
+                        }
+                        i += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sleeps for [`RECONNECT_DELAY`] and returns `true`, or returns `false`
+    /// without sleeping if `requests_rx` closes first, so a reconnect loop
+    /// can stop promptly once `shutdown` runs.
+    async fn sleep_or_stop(requests_rx: &mut mpsc::Receiver<Request>) -> bool {
+        select! {
+            _ = time::sleep(RECONNECT_DELAY) => true,
+            req = requests_rx.recv() => req.is_some(),
+        }
+    }
+
+    pub async fn shutdown(self) -> Result<()> {
+        // Explicitly drop request_tx so that the supervisor loop
+        // knows its done
+        drop(self.requests_tx);
+        self.join_handle.await??;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Engine for Arc<NATSEngine> {
+    async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        if !self.is_connected() {
+            return Err(anyhow!("nats disconnected; reconnect in progress"));
+        }
+        let (tx, rx) = oneshot::channel();
+        self.requests_tx
+            .send(Request::Get(Get {
+                path: path.to_string(),
+                tx,
+            }))
+            .await?;
+        // Subscribe after sending get so we are listening before we receive the response
+        self.requests_tx
+            .send(Request::Subscribe(path.to_string()))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    async fn set(&self, path: &str, value: Vec<u8>) -> Result<()> {
+        if !self.is_connected() {
+            return Err(anyhow!("nats disconnected; reconnect in progress"));
+        }
+        self.requests_tx
+            .send(Request::Publish(path.to_string(), value))
+            .await?;
+        Ok(())
+    }
+}
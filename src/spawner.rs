@@ -0,0 +1,160 @@
+//! Runtime-agnostic task spawning for the VM.
+//!
+//! `VM`/`Thread` need to fan a program out across concurrently running
+//! threads (see [`crate::compiler::Instruction::Spawn`]) without being hard-wired to
+//! a particular async runtime. A [`Spawner`] hands back an opaque
+//! [`TaskHandle`] that can be awaited for completion, so callers can plug in
+//! whatever executor their host process already runs.
+
+use anyhow::Result;
+use futures::future::{BoxFuture, FutureExt};
+use std::future::Future;
+
+/// A handle to a task spawned by a [`Spawner`], awaitable for completion.
+pub trait TaskHandle: Send {
+    fn join(self: Box<Self>) -> BoxFuture<'static, Result<()>>;
+}
+
+/// Spawns a boxed future onto some executor and returns a handle to it.
+///
+/// Implementations are cheap to clone (most wrap a channel or executor handle)
+/// since a fresh `Spawner` is threaded into every spawned [`crate::vm::Thread`].
+pub trait Spawner: Clone + Send + Sync {
+    fn spawn(&self, fut: BoxFuture<'static, Result<()>>) -> Box<dyn TaskHandle>;
+}
+
+/// The default [`Spawner`], backed by the ambient tokio runtime.
+#[derive(Clone, Copy, Default)]
+pub struct TokioSpawner;
+
+struct TokioTaskHandle(tokio::task::JoinHandle<Result<()>>);
+
+impl TaskHandle for TokioTaskHandle {
+    fn join(self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        async move { self.0.await? }.boxed()
+    }
+}
+
+impl Spawner for TokioSpawner {
+    fn spawn(&self, fut: BoxFuture<'static, Result<()>>) -> Box<dyn TaskHandle> {
+        Box::new(TokioTaskHandle(tokio::spawn(fut)))
+    }
+}
+
+/// A [`Spawner`] backed by a `smol`-style executor: futures are pushed onto an
+/// [`async_executor::Executor`] that is driven by a dedicated runner thread
+/// rather than the tokio runtime. This lets embedders run `dan` scenes inside
+/// a smol/async-std host, or any other single-threaded executor, without
+/// pulling in tokio's full feature set.
+#[derive(Clone)]
+pub struct ExecutorSpawner {
+    executor: std::sync::Arc<async_executor::Executor<'static>>,
+}
+
+impl ExecutorSpawner {
+    /// Spins up a runner thread driving a fresh `async_executor::Executor`
+    /// and returns a `Spawner` that schedules work onto it.
+    pub fn new() -> Self {
+        let executor = std::sync::Arc::new(async_executor::Executor::new());
+        let runner = executor.clone();
+        std::thread::spawn(move || {
+            futures_lite::future::block_on(runner.run(futures::future::pending::<()>()));
+        });
+        Self { executor }
+    }
+}
+
+impl Default for ExecutorSpawner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct ExecutorTaskHandle(async_executor::Task<Result<()>>);
+
+impl TaskHandle for ExecutorTaskHandle {
+    fn join(self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        async move { self.0.await }.boxed()
+    }
+}
+
+impl Spawner for ExecutorSpawner {
+    fn spawn(&self, fut: BoxFuture<'static, Result<()>>) -> Box<dyn TaskHandle> {
+        Box::new(ExecutorTaskHandle(self.executor.spawn(fut)))
+    }
+}
+
+/// A [`Spawner`] that batches wakeups into fixed time quanta instead of
+/// scheduling each spawned thread the instant it becomes ready.
+///
+/// A home with dozens of scenes spawns many threads that each mostly sleep on
+/// `wait`/`at`; waking each one the moment its timer expires causes a storm of
+/// individual wakeups. `ThrottlingSpawner` instead keeps every spawned future
+/// in a single ready-queue and, once per `throttling_interval`, drains the
+/// queue and polls everything in it exactly once, re-queueing whatever is
+/// still pending. This trades a bounded latency (at most one interval) for
+/// far fewer wakeups and lower idle CPU, which matters when the VM runs
+/// continuously on a small always-on controller.
+#[derive(Clone)]
+pub struct ThrottlingSpawner<S: Spawner> {
+    inner: S,
+    queue: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<QueuedTask>>>,
+}
+
+struct QueuedTask {
+    fut: BoxFuture<'static, Result<()>>,
+    done: tokio::sync::oneshot::Sender<Result<()>>,
+}
+
+impl<S: Spawner + 'static> ThrottlingSpawner<S> {
+    /// Wraps `inner` so every future spawned through the returned `Spawner` is
+    /// instead polled in batches every `throttling_interval`. The quantum
+    /// driver itself is spawned once, onto `inner`, and shared by every clone.
+    pub fn new(inner: S, throttling_interval: std::time::Duration) -> Self {
+        let queue: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<QueuedTask>>> =
+            Default::default();
+        inner.spawn(Self::drive(queue.clone(), throttling_interval).boxed());
+        Self { inner, queue }
+    }
+
+    async fn drive(
+        queue: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<QueuedTask>>>,
+        throttling_interval: std::time::Duration,
+    ) -> Result<()> {
+        let mut ticker = tokio::time::interval(throttling_interval);
+        let mut running: Vec<QueuedTask> = Vec::new();
+        let waker = futures::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        loop {
+            ticker.tick().await;
+            running.extend(queue.lock().unwrap().drain(..));
+
+            let mut i = 0;
+            while i < running.len() {
+                match running[i].fut.as_mut().poll(&mut cx) {
+                    std::task::Poll::Ready(result) => {
+                        let task = running.remove(i);
+                        let _ = task.done.send(result);
+                    }
+                    std::task::Poll::Pending => i += 1,
+                }
+            }
+        }
+    }
+}
+
+struct ThrottledTaskHandle(tokio::sync::oneshot::Receiver<Result<()>>);
+
+impl TaskHandle for ThrottledTaskHandle {
+    fn join(self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        async move { self.0.await? }.boxed()
+    }
+}
+
+impl<S: Spawner + 'static> Spawner for ThrottlingSpawner<S> {
+    fn spawn(&self, fut: BoxFuture<'static, Result<()>>) -> Box<dyn TaskHandle> {
+        let (done, rx) = tokio::sync::oneshot::channel();
+        self.queue.lock().unwrap().push_back(QueuedTask { fut, done });
+        Box::new(ThrottledTaskHandle(rx))
+    }
+}
@@ -1,10 +1,168 @@
-use chrono::{DateTime, Local};
-pub fn sunset(lat: f64, lon: f64, offset: f64) -> DateTime<Local> {
+//! Resolves `at`'s time literals — `#sunrise`/`#sunset`/`#dawn`/`#dusk`/
+//! `#solar_noon` and wall-clock times like `10:05PM` — into the next
+//! concrete instant they occur, against a configured (latitude, longitude)
+//! and IANA timezone (see [`crate::vm::Engine::location`]/
+//! [`crate::vm::Engine::timezone`]).
+//!
+//! The solar events follow the recurrence used by NOAA's solar calculator
+//! spreadsheet (the `d2`..`w2` variable names below mirror its cell
+//! references so the formulas stay easy to cross-check). They're computed
+//! per calendar day *in the configured timezone*, not the machine's own
+//! local timezone, so a script's behavior doesn't depend on where the
+//! process happens to run. Requires the `chrono-tz` crate for [`Tz`].
+
+use chrono::{
+    DateTime, Duration, Local, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+
+/// Zenith angle, in degrees, at which the sun is considered to have
+/// risen/set, accounting for atmospheric refraction and the sun's apparent
+/// radius.
+pub const ZENITH_OFFICIAL: f64 = 90.833;
+/// Zenith angle marking civil twilight (dawn/dusk): the horizon is still lit
+/// enough for most outdoor activities without artificial light.
+pub const ZENITH_CIVIL: f64 = 96.0;
+/// Zenith angle marking nautical twilight: the horizon is barely visible.
+pub const ZENITH_NAUTICAL: f64 = 102.0;
+/// Zenith angle marking astronomical twilight: the sky is effectively dark.
+pub const ZENITH_ASTRONOMICAL: f64 = 108.0;
+
+/// How many calendar days [`next_event`] will scan forward before giving up
+/// on finding an occurrence. A year comfortably covers the Arctic/Antarctic
+/// circles' longest polar night, where a rise/set event can be genuinely
+/// absent for weeks at a time.
+const MAX_SCAN_DAYS: i64 = 366;
+
+/// The next time the sun crosses [`ZENITH_OFFICIAL`] on its way up after
+/// `after`, or `None` if it doesn't within [`MAX_SCAN_DAYS`] (polar night
+/// lasting that long at this latitude).
+pub fn next_sunrise(lat: f64, lon: f64, tz: Tz, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    next_event(lat, lon, tz, after, |lat, lon, tz, date| {
+        rise_set(lat, lon, tz, date, ZENITH_OFFICIAL).map(|(rise, _)| rise)
+    })
+}
+
+/// The next time the sun crosses [`ZENITH_OFFICIAL`] on its way down after
+/// `after`, or `None` if it doesn't within [`MAX_SCAN_DAYS`].
+pub fn next_sunset(lat: f64, lon: f64, tz: Tz, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    next_event(lat, lon, tz, after, |lat, lon, tz, date| {
+        rise_set(lat, lon, tz, date, ZENITH_OFFICIAL).map(|(_, set)| set)
+    })
+}
+
+/// The next start of civil twilight (sun crossing [`ZENITH_CIVIL`] on its
+/// way up) after `after`, or `None` if it doesn't within [`MAX_SCAN_DAYS`].
+pub fn next_dawn(lat: f64, lon: f64, tz: Tz, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    next_event(lat, lon, tz, after, |lat, lon, tz, date| {
+        rise_set(lat, lon, tz, date, ZENITH_CIVIL).map(|(rise, _)| rise)
+    })
+}
+
+/// The next end of civil twilight (sun crossing [`ZENITH_CIVIL`] on its way
+/// down) after `after`, or `None` if it doesn't within [`MAX_SCAN_DAYS`].
+pub fn next_dusk(lat: f64, lon: f64, tz: Tz, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    next_event(lat, lon, tz, after, |lat, lon, tz, date| {
+        rise_set(lat, lon, tz, date, ZENITH_CIVIL).map(|(_, set)| set)
+    })
+}
+
+/// The next solar noon (the sun's highest point in the sky) after `after`.
+/// Unlike the rise/set events this always occurs, so there is no polar
+/// edge case and no `Option`.
+pub fn next_solar_noon(lat: f64, lon: f64, tz: Tz, after: DateTime<Local>) -> DateTime<Local> {
+    next_event(lat, lon, tz, after, |lat, lon, tz, date| {
+        Some(solar_noon(lat, lon, tz, date))
+    })
+    .expect("solar noon occurs every day, so next_event always finds one within MAX_SCAN_DAYS")
+}
+
+/// The next future occurrence of `hour:minute` in `tz` after `after`.
+/// Handles both kinds of DST transition: an ambiguous local time (the
+/// repeated hour when clocks fall back) resolves to its earlier instance;
+/// a nonexistent one (the skipped hour when clocks spring forward)
+/// resolves to the instant the clocks jump to.
+pub fn next_clock_time(tz: Tz, hour: u32, minute: u32, after: DateTime<Local>) -> DateTime<Local> {
+    let mut date = after.with_timezone(&tz).date_naive();
+    loop {
+        let naive = date
+            .and_hms_opt(hour, minute, 0)
+            .expect("caller validates hour/minute are in range");
+        let candidate = resolve_local(tz, naive);
+        if candidate > after {
+            return candidate;
+        }
+        date = date
+            .succ_opt()
+            .expect("NaiveDate has no reachable upper bound here");
+    }
 }
-fn _do(lat: f64, lon: f64, offset: f64) ->  {
-    let today = Local::today();
 
-    let d2 = 0.0; // d2 is the current date, # of days since Jan 1 1900?
+/// Scans forward from `after`'s own calendar day (in `tz`) for up to
+/// [`MAX_SCAN_DAYS`], returning the first instant `event` reports for a day
+/// that is strictly after `after`. `event` computes the occurrence (if any)
+/// for a single given date, e.g. [`rise_set`] for solar rise/set events.
+fn next_event(
+    lat: f64,
+    lon: f64,
+    tz: Tz,
+    after: DateTime<Local>,
+    event: impl Fn(f64, f64, Tz, NaiveDate) -> Option<DateTime<Local>>,
+) -> Option<DateTime<Local>> {
+    let mut date = after.with_timezone(&tz).date_naive();
+    for _ in 0..MAX_SCAN_DAYS {
+        if let Some(at) = event(lat, lon, tz, date) {
+            if at > after {
+                return Some(at);
+            }
+        }
+        date = date.succ_opt()?;
+    }
+    None
+}
+
+/// Solar noon on `date`: the moment the sun is highest in the sky.
+fn solar_noon(lat: f64, lon: f64, tz: Tz, date: NaiveDate) -> DateTime<Local> {
+    let (x2, _t2) = noon_and_declination(lat, lon, tz, date);
+    from_day_fraction(tz, date, x2)
+}
+
+/// Computes the (sunrise, sunset) pair on `date` for the given `zenith`, or
+/// `None` if the sun never crosses it that day, i.e. the `acos` term below
+/// falls outside `[-1, 1]` (polar day or night).
+fn rise_set(
+    lat: f64,
+    lon: f64,
+    tz: Tz,
+    date: NaiveDate,
+    zenith: f64,
+) -> Option<(DateTime<Local>, DateTime<Local>)> {
+    let (x2, t2) = noon_and_declination(lat, lon, tz, date);
+
+    let acos_arg = f64::cos(radians(zenith)) / (f64::cos(radians(lat)) * f64::cos(radians(t2)))
+        - f64::tan(radians(lat)) * f64::tan(radians(t2));
+    if !(-1.0..=1.0).contains(&acos_arg) {
+        return None;
+    }
+    let w2 = degrees(f64::acos(acos_arg));
+
+    let sunrise = x2 - w2 * 4.0 / 1440.0;
+    let sunset = x2 + w2 * 4.0 / 1440.0;
+    Some((
+        from_day_fraction(tz, date, sunrise),
+        from_day_fraction(tz, date, sunset),
+    ))
+}
+
+/// Runs the shared NOAA recurrence through `x2` (solar noon, as a fraction
+/// of `date`) and `t2` (the sun's declination), which every event above is
+/// derived from.
+fn noon_and_declination(lat: f64, lon: f64, tz: Tz, date: NaiveDate) -> (f64, f64) {
+    // d2 is the day count since the NOAA spreadsheet's epoch of 1899-12-30.
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30).unwrap();
+    let d2 = (date - epoch).num_days() as f64;
+    let offset = tz.offset_from_utc_date(&date).fix().local_minus_utc() as f64 / 3600.0;
+
     let f2 = d2 + 2415018.5 - offset / 24.0;
     let g2 = (f2 - 2451545.0) / 36525.0;
     let i2 = (280.46646 + g2 * (36000.76983 + g2 * 0.0003032)) % 360.0;
@@ -15,16 +173,10 @@ fn _do(lat: f64, lon: f64, offset: f64) ->  {
         + f64::sin(radians(3.0 * j2)) * 0.000289;
     let m2 = i2 + l2;
     let n2 = j2 + l2;
-    let o2 = (1.000001018 * (1.0 - k2 * k2)) / (1.0 + k2 * f64::cos(radians(n2)));
     let p2 = m2 - 0.00569 - 0.00478 * f64::sin(radians(125.04 - 1934.136 * g2));
     let q2 =
         23.0 + (26.0 + (21.448 - g2 * (46.815 + g2 * (0.00059 - g2 * 0.001813))) / 60.0) / 60.0;
     let r2 = q2 + 0.00256 * f64::cos(radians(125.04 - 1934.136 * g2));
-    // TODO is atan2 arg order correct?
-    let s2 = degrees(f64::atan2(
-        f64::cos(radians(p2)),
-        f64::cos(radians(r2)) * f64::sin(radians(p2)),
-    ));
     let t2 = degrees(f64::asin(f64::sin(radians(r2)) * f64::sin(radians(p2))));
     let u2 = f64::tan(radians(r2 / 2.0)) * f64::tan(radians(r2 / 2.0));
     let v2 = 4.0
@@ -34,13 +186,41 @@ fn _do(lat: f64, lon: f64, offset: f64) ->  {
                 - 0.5 * u2 * u2 * f64::sin(4.0 * radians(i2))
                 - 1.25 * k2 * k2 * f64::sin(2.0 * radians(j2)),
         );
-    let w2 = degrees(f64::acos(
-        f64::cos(radians(90.833)) / (f64::cos(radians(lat)) * f64::cos(radians(t2)))
-            - f64::tan(radians(lat)) * f64::tan(radians(t2)),
-    ));
     let x2 = (720.0 - 4.0 * lon - v2 + offset * 60.0) / 1440.0;
-    let sunrise = x2 - w2 * 4.0 / 1440.0;
-    let sunset = x2 + w2 * 4.0 / 1440.0;
+    let _ = (m2, n2); // only used to derive p2/t2 above
+
+    (x2, t2)
+}
+
+/// Maps a fraction of a day (`0.0` = midnight, `0.5` = noon) back to a
+/// `DateTime` on `date` in `tz`.
+fn from_day_fraction(tz: Tz, date: NaiveDate, frac: f64) -> DateTime<Local> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    let seconds = (frac * 86_400.0).round() as i64;
+    resolve_local(tz, midnight + Duration::seconds(seconds))
+}
+
+/// Resolves a naive (timezone-less) date and time as a wall-clock instant
+/// in `tz`, handling the two kinds of DST transition: an ambiguous time
+/// (clocks fell back, so it occurs twice) resolves to its earlier instance;
+/// a nonexistent one (clocks sprang forward over it) resolves to the
+/// instant the clocks jump to, i.e. the first whole wall-clock minute after
+/// it that exists in `tz`.
+fn resolve_local(tz: Tz, naive: NaiveDateTime) -> DateTime<Local> {
+    match tz.from_local_datetime(&naive) {
+        chrono::LocalResult::Single(dt) => dt,
+        chrono::LocalResult::Ambiguous(earliest, _latest) => earliest,
+        chrono::LocalResult::None => {
+            let mut probe = naive.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap();
+            loop {
+                probe += Duration::minutes(1);
+                if let Some(dt) = tz.from_local_datetime(&probe).earliest() {
+                    break dt;
+                }
+            }
+        }
+    }
+    .with_timezone(&Local)
 }
 
 fn radians(deg: f64) -> f64 {
@@ -50,23 +230,94 @@ fn degrees(rad: f64) -> f64 {
     rad / std::f64::consts::PI * 180.0
 }
 
-//f2 = D2+2415018.5-$B$5/24
-//g2 = (F2-2451545)/36525
-//i2 = MOD(280.46646+G2*(36000.76983+G2*0.0003032),360)
-//j2 = 357.52911+G2*(35999.05029-0.0001537*G2)
-//k2 = 0.016708634-G2*(0.000042037+0.0000001267*G2)
-//l2 = SIN(RADIANS(J2))*(1.914602-G2*(0.004817+0.000014*G2))+SIN(RADIANS(2*J2))*(0.019993-0.000101*G2)+SIN(RADIANS(3*J2))*0.000289
-//m2 = I2+L2
-//n2 = J2+L2
-//o2 = (1.000001018*(1-K2*K2))/(1+K2*COS(RADIANS(N2)))
-//p2 = M2-0.00569-0.00478*SIN(RADIANS(125.04-1934.136*G2))
-//q2 = 23+(26+((21.448-G2*(46.815+G2*(0.00059-G2*0.001813))))/60)/60
-//r2 = Q2+0.00256*COS(RADIANS(125.04-1934.136*G2))
-//s2 = DEGREES(ATAN2(COS(RADIANS(P2)),COS(RADIANS(R2))*SIN(RADIANS(P2))))
-//t2 = DEGREES(ASIN(SIN(RADIANS(R2))*SIN(RADIANS(P2))))
-//u2 = TAN(RADIANS(R2/2))*TAN(RADIANS(R2/2))
-//v2 = 4*DEGREES(U2*SIN(2*RADIANS(I2))-2*K2*SIN(RADIANS(J2))+4*K2*U2*SIN(RADIANS(J2))*COS(2*RADIANS(I2))-0.5*U2*U2*SIN(4*RADIANS(I2))-1.25*K2*K2*SIN(2*RADIANS(J2)))
-//w2 = DEGREES(ACOS(COS(RADIANS(90.833))/(COS(RADIANS(lat))*COS(RADIANS(T2)))-TAN(RADIANS(lat))*TAN(RADIANS(T2))))
-//x2 =(720-4*lon-V2+offset_east*60)/1440
-//y2 = X2-W2*4/1440 // sunrize
-//z2 = x2 + w2*4/1440 //sunset
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_clock_time_rolls_to_tomorrow_when_passed() {
+        let tz = chrono_tz::UTC;
+        let now = Utc
+            .with_ymd_and_hms(2024, 6, 1, 23, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let next = next_clock_time(tz, 10, 0, now);
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 6, 2, 10, 0, 0)
+                .unwrap()
+                .with_timezone(&Local),
+            next
+        );
+    }
+
+    #[test]
+    fn test_next_clock_time_same_day_when_still_ahead() {
+        let tz = chrono_tz::UTC;
+        let now = Utc
+            .with_ymd_and_hms(2024, 6, 1, 1, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let next = next_clock_time(tz, 10, 0, now);
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 6, 1, 10, 0, 0)
+                .unwrap()
+                .with_timezone(&Local),
+            next
+        );
+    }
+
+    #[test]
+    fn test_next_clock_time_skips_nonexistent_spring_forward_hour() {
+        // US Eastern sprang forward at 2024-03-10 02:00 -> 03:00; 2:30AM
+        // never happens that day.
+        let tz: Tz = chrono_tz::America::New_York;
+        let now = tz
+            .with_ymd_and_hms(2024, 3, 10, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let next = next_clock_time(tz, 2, 30, now);
+        assert_eq!(
+            tz.with_ymd_and_hms(2024, 3, 10, 3, 0, 0)
+                .unwrap()
+                .with_timezone(&Local),
+            next
+        );
+    }
+
+    #[test]
+    fn test_rise_set_polar_night_returns_none() {
+        // Deep into Arctic polar night: the sun doesn't cross the official
+        // zenith at all.
+        assert_eq!(
+            None,
+            rise_set(
+                89.0,
+                0.0,
+                chrono_tz::UTC,
+                NaiveDate::from_ymd_opt(2024, 12, 21).unwrap(),
+                ZENITH_OFFICIAL,
+            )
+        );
+    }
+
+    #[test]
+    fn test_next_sunrise_skips_polar_night_to_find_one() {
+        // At 89N, late December has no sunrise; scanning forward into
+        // spring should still find one within MAX_SCAN_DAYS.
+        let after = Utc
+            .with_ymd_and_hms(2024, 12, 21, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        assert!(next_sunrise(89.0, 0.0, chrono_tz::UTC, after).is_some());
+    }
+
+    #[test]
+    fn test_next_solar_noon_is_after_given_instant() {
+        let after = Utc
+            .with_ymd_and_hms(2024, 6, 1, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let noon = next_solar_noon(45.0, -75.0, chrono_tz::America::New_York, after);
+        assert!(noon > after);
+    }
+}
@@ -0,0 +1,379 @@
+//! A scenario-driven test harness for exercising a compiled dan [`Code`]
+//! against a virtual clock and an in-memory [`Engine`], instead of a real
+//! MQTT/NATS broker — similar in spirit to the connect/expect/message step
+//! vocabulary FIX test runners use. [`vm::tests::TestEngine`] and
+//! [`vm::tests::MockClock`] already cover this ground for this crate's own
+//! tests; this module generalizes that pattern into something `pub`, driven
+//! by a scenario parsed from text rather than hand-written Rust, so a dan
+//! script author can regression-test `scene`/`when`/`wait`/`at` timing
+//! without touching this crate's internals.
+//!
+//! A scenario is a sequence of lines, one step each:
+//!
+//! ```text
+//! publish <path> <value>
+//! advance <duration>
+//! expect set <path> <value>
+//! expect no-op
+//! ```
+//!
+//! `publish` simulates a message arriving from outside (waking any `when`/
+//! `watch` on that path); `advance` moves the virtual clock, firing any
+//! `wait`/`at` deadline that has now elapsed; `expect set` asserts the
+//! script's next `set` call, in order, wrote the given value to the given
+//! path; `expect no-op` asserts it made no `set` call since the last
+//! `expect`. Blank lines and lines starting with `#` are ignored.
+
+use crate::compiler::Code;
+use crate::vm::{Engine, VM};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use futures::stream::{self, BoxStream};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// One step of a [`Scenario`]. See the module documentation for the text
+/// format [`parse_scenario`] reads these from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Publish(String, String),
+    Advance(Duration),
+    ExpectSet(String, String),
+    ExpectNoOp,
+}
+
+/// A parsed scenario: an ordered list of [`Step`]s, run against a
+/// [`MockEngine`] by [`run_scenario`].
+pub type Scenario = Vec<Step>;
+
+/// Parses a scenario file's contents into a [`Scenario`]. See the module
+/// documentation for the line format; returns an error naming the bad line
+/// and why, rather than panicking, since a malformed scenario file is a
+/// user mistake, not a bug in the harness.
+pub fn parse_scenario(text: &str) -> Result<Scenario> {
+    let mut steps = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let step = match words.as_slice() {
+            ["publish", path, value] => Step::Publish(path.to_string(), value.to_string()),
+            ["advance", duration] => Step::Advance(parse_scenario_duration(duration)?),
+            ["expect", "set", path, value] => Step::ExpectSet(path.to_string(), value.to_string()),
+            ["expect", "no-op"] => Step::ExpectNoOp,
+            _ => bail!("scenario line {}: unrecognized step '{}'", lineno + 1, line),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+/// Parses a duration for a scenario file, e.g. `5m`, `30s`, `1h`: a whole
+/// number followed by exactly one `s`, `m` or `h` unit. This is
+/// deliberately separate from `compiler`'s own duration-literal parsing: a
+/// scenario file is describing how far to move the test's virtual clock,
+/// not writing a dan script, so it isn't bound by the DSL's grammar.
+fn parse_scenario_duration(s: &str) -> Result<Duration> {
+    let (digits, unit) = s.split_at(
+        s.find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("malformed duration '{s}': missing unit"))?,
+    );
+    let count: u64 = digits
+        .parse()
+        .map_err(|_| anyhow!("malformed duration '{s}': '{digits}' is not a whole number"))?;
+    let secs = match unit {
+        "s" => count,
+        "m" => count * 60,
+        "h" => count * 60 * 60,
+        _ => bail!("malformed duration '{s}': unknown unit '{unit}' (expected s, m, or h)"),
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+/// An [`Engine`] backed by an in-memory state map and a virtual clock
+/// instead of a real broker, so a [`Scenario`] can drive and assert a
+/// script's behavior deterministically. `get`/`watch` are served from
+/// [`publish`](MockEngine::publish)ed state; every `set` the script under
+/// test performs is appended to a log that [`run_scenario`]'s `expect`
+/// steps consume in order.
+pub struct MockEngine {
+    state: Mutex<MockEngineState>,
+}
+
+struct MockEngineState {
+    values: HashMap<String, Vec<u8>>,
+    watchers: HashMap<String, Vec<mpsc::UnboundedSender<Vec<u8>>>>,
+    set_log: VecDeque<(String, Vec<u8>)>,
+    now: DateTime<Local>,
+    pending: Vec<(DateTime<Local>, oneshot::Sender<()>)>,
+    location: (f64, f64),
+}
+
+impl MockEngine {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(MockEngineState {
+                values: HashMap::new(),
+                watchers: HashMap::new(),
+                set_log: VecDeque::new(),
+                now: Local::now(),
+                pending: Vec::new(),
+                location: (0.0, 0.0),
+            }),
+        })
+    }
+
+    /// Overrides the (latitude, longitude) [`Engine::location`] reports, so
+    /// a scenario can exercise `at #sunrise`/`at #sunset` for a specific
+    /// installation.
+    pub fn with_location(&self, lat: f64, lon: f64) {
+        self.state.lock().unwrap().location = (lat, lon);
+    }
+
+    /// Simulates a message arriving from outside the script under test:
+    /// updates the state `path` resolves to and wakes every active
+    /// `when`/`watch` on it.
+    pub fn publish(&self, path: &str, value: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.values.insert(path.to_string(), value.clone());
+        if let Some(senders) = state.watchers.get_mut(path) {
+            senders.retain(|tx| tx.send(value.clone()).is_ok());
+        }
+    }
+
+    /// Moves the virtual clock forward by `d`, firing every pending `wait`
+    /// whose deadline has now elapsed, in deadline order. Mirrors
+    /// `vm::tests::MockClock::advance`.
+    pub fn advance(&self, d: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += chrono::Duration::from_std(d).unwrap();
+        let now = state.now;
+        state.pending.sort_by_key(|(deadline, _)| *deadline);
+        let mut i = 0;
+        while i < state.pending.len() {
+            if state.pending[i].0 <= now {
+                let (_, tx) = state.pending.remove(i);
+                let _ = tx.send(());
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Pops the oldest recorded `set` call, or `None` if the script hasn't
+    /// made one since the last pop.
+    fn pop_set(&self) -> Option<(String, Vec<u8>)> {
+        self.state.lock().unwrap().set_log.pop_front()
+    }
+
+    /// Whether the script has made a `set` call since the last pop.
+    fn has_pending_set(&self) -> bool {
+        !self.state.lock().unwrap().set_log.is_empty()
+    }
+}
+
+#[async_trait]
+impl Engine for Arc<MockEngine> {
+    async fn now(&self) -> DateTime<Local> {
+        self.state.lock().unwrap().now
+    }
+
+    async fn wait(&self, d: Duration) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            let deadline = state.now + chrono::Duration::from_std(d).unwrap();
+            state.pending.push((deadline, tx));
+        }
+        rx.await?;
+        Ok(())
+    }
+
+    async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        self.state
+            .lock()
+            .unwrap()
+            .values
+            .get(path)
+            .cloned()
+            .ok_or_else(|| anyhow!("no value published for '{path}'"))
+    }
+
+    async fn set(&self, path: &str, value: Vec<u8>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.values.insert(path.to_string(), value.clone());
+        state.set_log.push_back((path.to_string(), value));
+        Ok(())
+    }
+
+    fn location(&self) -> (f64, f64) {
+        self.state.lock().unwrap().location
+    }
+
+    async fn watch(&self, path: &str) -> Result<BoxStream<'static, Vec<u8>>> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.state
+            .lock()
+            .unwrap()
+            .watchers
+            .entry(path.to_string())
+            .or_default()
+            .push(tx);
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+/// Runs `code` against a fresh [`MockEngine`], driving it through every
+/// step of `scenario` in order, and returns an error on the first `expect`
+/// that doesn't hold. Once every step has run it signals the VM to shut
+/// down and returns, so nothing past the last step is observed; callers
+/// that need to assert more should add a trailing `advance`/`expect`.
+pub async fn run_scenario(code: Code, scenario: &Scenario) -> Result<()> {
+    let engine = MockEngine::new();
+    let vm = VM::new(engine.clone());
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    tokio::spawn(async move {
+        let _ = vm.run(code, shutdown_rx).await;
+    });
+    // Let every spawned thread reach its first `wait`/`watch`/`get` before
+    // the first step runs, the same way `vm::tests` settles a freshly
+    // spawned VM.
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+
+    for (i, step) in scenario.iter().enumerate() {
+        match step {
+            Step::Publish(path, value) => {
+                // Payloads are decoded as JSON, so a bare word such as `on`
+                // is published as the JSON string `"on"`.
+                let payload = match serde_json::from_str::<serde_json::Value>(value) {
+                    Ok(_) => value.as_bytes().to_vec(),
+                    Err(_) => serde_json::to_vec(value)?,
+                };
+                engine.publish(path, payload);
+            }
+            Step::Advance(d) => {
+                engine.advance(*d);
+            }
+            Step::ExpectSet(path, value) => {
+                let (got_path, got_value) = engine
+                    .pop_set()
+                    .ok_or_else(|| anyhow!("step {}: expected a set, got none", i + 1))?;
+                let got_value = String::from_utf8(got_value)
+                    .map_err(|_| anyhow!("step {}: set value was not utf-8", i + 1))?;
+                if &got_path != path || &got_value != value {
+                    bail!(
+                        "step {}: expected set {} {}, got set {} {}",
+                        i + 1,
+                        path,
+                        value,
+                        got_path,
+                        got_value
+                    );
+                }
+            }
+            Step::ExpectNoOp => {
+                if engine.has_pending_set() {
+                    let (path, _) = engine.pop_set().unwrap();
+                    bail!("step {}: expected no-op, but got a set to {}", i + 1, path);
+                }
+            }
+        }
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+    let _ = shutdown_tx.send(());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Interpreter;
+    use crate::Compile;
+
+    #[test]
+    fn test_parse_scenario() {
+        let text = "
+            # a comment
+            publish light/kitchen on
+
+            advance 5m
+            expect set light/hallway on
+            expect no-op
+        ";
+        assert_eq!(
+            vec![
+                Step::Publish("light/kitchen".to_string(), "on".to_string()),
+                Step::Advance(Duration::from_secs(300)),
+                Step::ExpectSet("light/hallway".to_string(), "on".to_string()),
+                Step::ExpectNoOp,
+            ],
+            parse_scenario(text).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_scenario_rejects_unknown_step() {
+        assert!(parse_scenario("frobnicate light/kitchen").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_scenario_wait_fires_on_advance() {
+        let code = Interpreter::from_source(
+            r#"
+            wait 300s set [light/hallway] "on";
+        "#,
+        )
+        .unwrap();
+        let scenario = parse_scenario(
+            "
+            expect no-op
+            advance 5m
+            expect set light/hallway on
+        ",
+        )
+        .unwrap();
+        run_scenario(code, &scenario).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_scenario_when_fires_on_publish() {
+        let code = Interpreter::from_source(
+            r#"
+            when <light/kitchen> is "on" set [light/hallway] "on";
+        "#,
+        )
+        .unwrap();
+        let scenario = parse_scenario(
+            "
+            expect no-op
+            publish light/kitchen on
+            expect set light/hallway on
+        ",
+        )
+        .unwrap();
+        run_scenario(code, &scenario).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_scenario_reports_mismatched_expect() {
+        let code = Interpreter::from_source(
+            r#"
+            set [light/hallway] "off";
+        "#,
+        )
+        .unwrap();
+        let scenario = parse_scenario("expect set light/hallway on").unwrap();
+        let err = run_scenario(code, &scenario).await.unwrap_err();
+        assert!(err.to_string().contains("expected set"));
+    }
+}
@@ -4,8 +4,11 @@ use {
     anyhow::Result,
     async_trait::async_trait,
     chrono::{DateTime, Local},
-    futures::future::{BoxFuture, FutureExt},
-    std::{convert::TryInto, fmt, sync::Arc, time::Duration},
+    futures::{
+        future::{self, BoxFuture, FutureExt},
+        stream::{BoxStream, FuturesUnordered, StreamExt},
+    },
+    std::{collections::VecDeque, convert::TryInto, fmt, sync::Arc, time::Duration},
     tokio::{
         io::AsyncWriteExt,
         select,
@@ -13,7 +16,6 @@ use {
             broadcast,
             mpsc::{self, Sender},
         },
-        task::JoinHandle,
         time,
     },
 };
@@ -21,9 +23,137 @@ use {
 use tokio::io;
 
 use crate::compiler::{Code, Instruction, TimeOfDay, Value};
+use crate::fault::Fault;
+use crate::spawner::{Spawner, TaskHandle, ThrottlingSpawner, TokioSpawner};
 
 const STACK_SIZE: usize = 512;
 
+/// Errors raised by `ThreadContext`'s operand stack and bytecode dispatch
+/// itself, as opposed to an `Engine` call failing or a semantic mistake
+/// `check::check` should already have caught before compilation. Every
+/// variant carries the instruction pointer that was executing when it
+/// fired, so `_run`/`VM::run` can report which instruction a malformed
+/// program tripped over instead of aborting the thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    /// `push` found the operand stack already at `STACK_SIZE`.
+    StackOverflow { ip: usize },
+    /// `pop`/`pick` was asked for a value past the bottom of the stack.
+    StackUnderflow { ip: usize },
+    /// A value popped off the stack wasn't the variant the instruction at
+    /// `ip` required.
+    TypeMismatch {
+        ip: usize,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// `Div`/`Mod` at `ip` found a zero integer divisor. Floats divide by
+    /// zero without panicking (producing `inf`/`NaN`), so only the integer
+    /// and duration-by-integer forms need this check.
+    DivideByZero { ip: usize },
+    /// `Index` at `ip` indexed a `Value::Array` of length `len` with an
+    /// out-of-range `index`.
+    IndexOutOfRange { ip: usize, index: usize, len: usize },
+    /// An arithmetic instruction at `ip` overflowed or underflowed —
+    /// `i64`/`Duration` addition, subtraction, or multiplication wrapped
+    /// past its bounds, or an `Integer` operand used to scale a `Duration`
+    /// didn't fit the `u32` that scaling needs.
+    Overflow { ip: usize },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::StackOverflow { ip } => {
+                write!(f, "inst[{ip}]: operand stack overflow (limit {STACK_SIZE})")
+            }
+            VmError::StackUnderflow { ip } => write!(f, "inst[{ip}]: operand stack underflow"),
+            VmError::TypeMismatch {
+                ip,
+                expected,
+                found,
+            } => write!(f, "inst[{ip}]: expected {expected}, found {found}"),
+            VmError::DivideByZero { ip } => write!(f, "inst[{ip}]: division by zero"),
+            VmError::IndexOutOfRange { ip, index, len } => write!(
+                f,
+                "inst[{ip}]: array index {index} out of range (length {len})"
+            ),
+            VmError::Overflow { ip } => write!(f, "inst[{ip}]: arithmetic overflow"),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// Whether `value` is one of `Add`/`Sub`/`Mul`/`Div`'s numeric-or-duration
+/// operand shapes, for picking which of a mismatched pair of operands is
+/// the actual offender in [`VmError::TypeMismatch`]'s `found` field.
+fn is_numeric_or_duration(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Integer(_) | Value::Float(_) | Value::Duration(_)
+    )
+}
+
+/// A human-readable name for the variant of a `Value`, for
+/// [`VmError::TypeMismatch`]'s `found` field.
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Str(_) => "string",
+        Value::Path(_) => "path",
+        Value::Duration(_) => "duration",
+        Value::Time(_) => "time",
+        Value::Float(_) => "float",
+        Value::Integer(_) => "integer",
+        Value::Bool(_) => "bool",
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::Jump(_) => "jump target",
+        Value::Fault(_) => "fault",
+    }
+}
+
+/// Orders `lhs`/`rhs` for `Instruction::LessThan`/`GreaterThan`/`LessEqual`/
+/// `GreaterEqual`. Integers and floats promote like the arithmetic
+/// instructions do, and `Duration`s compare natively; a [`TimeOfDay::HM`]
+/// pair compares as wall-clock time, but any other `Time` combination
+/// (`Sunrise` vs `HM(6, 0)`, say) has no context-free ordering, and neither
+/// does a `NaN` float, so both return a `VmError::TypeMismatch` at `ip` like
+/// every other type mismatch here instead of panicking.
+fn ordered(lhs: Value, rhs: Value, ip: usize) -> std::result::Result<std::cmp::Ordering, VmError> {
+    let not_a_number = || VmError::TypeMismatch {
+        ip,
+        expected: "comparable numbers",
+        found: "NaN",
+    };
+    match (lhs, rhs) {
+        (Value::Integer(l), Value::Integer(r)) => Ok(l.cmp(&r)),
+        (Value::Float(l), Value::Float(r)) => l.partial_cmp(&r).ok_or_else(not_a_number),
+        (Value::Integer(l), Value::Float(r)) => (l as f64).partial_cmp(&r).ok_or_else(not_a_number),
+        (Value::Float(l), Value::Integer(r)) => l.partial_cmp(&(r as f64)).ok_or_else(not_a_number),
+        (Value::Duration(l), Value::Duration(r)) => Ok(l.cmp(&r)),
+        (Value::Time(TimeOfDay::HM(h1, m1)), Value::Time(TimeOfDay::HM(h2, m2))) => {
+            Ok((h1, m1).cmp(&(h2, m2)))
+        }
+        (a, b) => Err(VmError::TypeMismatch {
+            ip,
+            expected: "numbers, durations, or wall-clock times",
+            found: if is_numeric_or_duration(&a) {
+                describe(&b)
+            } else {
+                describe(&a)
+            },
+        }),
+    }
+}
+
+/// How many nested `func` calls may be outstanding at once before
+/// `Instruction::CallFn` gives up rather than growing `fn_frames` forever —
+/// named after the wasmi runner's constant of the same purpose, since an
+/// unbounded recursive `func` is the same failure mode as unbounded wasm
+/// recursion: it would otherwise just keep consuming stack slots.
+const DEFAULT_CALL_STACK_LIMIT: usize = 64;
+
 #[async_trait]
 pub trait Engine: Clone + Send + Sync {
     async fn print(&self, msg: &str) -> Result<()> {
@@ -33,35 +163,161 @@ pub trait Engine: Clone + Send + Sync {
         stdout.flush().await?;
         Ok(())
     }
+    /// The engine's notion of the current instant. Instructions that need to
+    /// reason about time (`at`, `sequence` steps) go through this rather than calling
+    /// `Local::now()` directly, so a test engine can substitute a virtual
+    /// clock and drive scheduling deterministically.
+    async fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
     async fn wait(&self, d: Duration) -> Result<()> {
         time::sleep(d).await;
         Ok(())
     }
     async fn get(&self, path: &str) -> Result<Vec<u8>>;
     async fn set(&self, path: &str, value: Vec<u8>) -> Result<()>;
+    /// Whether this engine's backing connection is currently usable. Defaults
+    /// to always-healthy for engines with nothing to disconnect from; an
+    /// engine backed by a real broker (e.g. [`crate::mqtt_engine::MQTTEngine`])
+    /// overrides this so a caller can observe an outage without having to
+    /// provoke one through a failed `get`/`set`.
+    async fn health(&self) -> bool {
+        true
+    }
+    /// The installation's (latitude, longitude) in degrees, used to resolve
+    /// solar time-of-day values (`#sunrise`, `#sunset`, `#dawn`, `#dusk`,
+    /// `#solar_noon`) in `at` statements. Defaults to 0°N 0°E for engines
+    /// that don't care about solar scheduling.
+    fn location(&self) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+    /// The IANA timezone `at`'s solar events and wall-clock literals
+    /// (`10:05PM`) resolve against, alongside [`Self::location`]. Defaults
+    /// to UTC for engines that don't care about a specific timezone.
+    fn timezone(&self) -> chrono_tz::Tz {
+        chrono_tz::UTC
+    }
+    /// Subscribes to changes on `path`, returning a stream of every value
+    /// subsequently published there. Backing a `when` guard with this instead
+    /// of repeated `get` polls means the thread only wakes on a real change.
+    ///
+    /// The default falls back to polling `get` once per stream item, so
+    /// engines that have no native subscription mechanism still work, just
+    /// without the latency/throughput benefits of a push-based one.
+    async fn watch(&self, path: &str) -> Result<BoxStream<'static, Vec<u8>>>
+    where
+        Self: 'static,
+    {
+        let engine = self.clone();
+        let path = path.to_string();
+        Ok(Box::pin(futures::stream::unfold(
+            (engine, path),
+            |(engine, path)| async move {
+                match engine.get(path.as_str()).await {
+                    Ok(value) => Some((value, (engine, path))),
+                    Err(_) => None,
+                }
+            },
+        )))
+    }
+    /// The running count of arrivals on `path` within the trailing `window`,
+    /// re-emitted every time a new arrival or an eviction changes it. Built
+    /// on top of [`Self::watch`], so engines get this for free; values are
+    /// carried as the decimal string form of the count so it flows through
+    /// the same `Vec<u8>`-typed stream/`Await` machinery as everything else.
+    async fn watch_count(&self, path: &str, window: Duration) -> Result<BoxStream<'static, Vec<u8>>>
+    where
+        Self: 'static,
+    {
+        let inner = self.watch(path).await?;
+        let arrivals: VecDeque<time::Instant> = VecDeque::new();
+        Ok(Box::pin(futures::stream::unfold(
+            (inner, arrivals),
+            move |(mut inner, mut arrivals)| async move {
+                loop {
+                    let next_expiry = arrivals.front().map(|t| *t + window);
+                    select! {
+                        item = inner.next() => match item {
+                            Some(_) => arrivals.push_back(time::Instant::now()),
+                            None => return None,
+                        },
+                        _ = Self::sleep_until(next_expiry) => {}
+                    }
+                    let now = time::Instant::now();
+                    while matches!(arrivals.front(), Some(t) if now.duration_since(*t) >= window) {
+                        arrivals.pop_front();
+                    }
+                    let count = arrivals.len();
+                    return Some((count.to_string().into_bytes(), (inner, arrivals)));
+                }
+            },
+        )))
+    }
+    /// Sleeps until `deadline`, or forever if there is none — lets
+    /// [`Self::watch_count`]'s `select!` wait on an eviction deadline that
+    /// may not exist yet without special-casing the empty-window case.
+    async fn sleep_until(deadline: Option<time::Instant>)
+    where
+        Self: Sized,
+    {
+        match deadline {
+            Some(deadline) => time::sleep_until(deadline).await,
+            None => future::pending().await,
+        }
+    }
 }
 
-struct Thread<E: Engine> {
+struct Thread<E: Engine, S: Spawner> {
     cancel_rx: broadcast::Receiver<()>,
-    ctx: ThreadContext<E>,
+    ctx: ThreadContext<E, S>,
 }
-struct ThreadContext<E: Engine> {
+struct ThreadContext<E: Engine, S: Spawner> {
     id: usize,
     engine: E,
+    spawner: S,
     code: Arc<Code>,
     ip: usize,
-    stack: [Value; STACK_SIZE],
-    stack_ptr: usize, // points to the next free space
+    /// The instruction pointer `step` is currently executing, i.e. `ip`
+    /// before it was advanced past this instruction — stashed here so
+    /// `push`/`pop`/`pick` can stamp it onto a `VmError` without every call
+    /// site having to thread it through by hand.
+    cur_ip: usize,
+    /// Bounded to `STACK_SIZE` by `push`: a plain growable `Vec` rather than
+    /// a fixed `[Value; STACK_SIZE]`, since `Value` isn't `Copy` and a
+    /// zeroed array of it is never valid (a zeroed `String`/`Duration` is
+    /// undefined behavior the moment anything reads or drops it).
+    stack: Vec<Value>,
     call_stack: Vec<usize>,
-    sender: Sender<JoinHandle<Result<()>>>,
+    fn_frames: Vec<FnFrame>,
+    sender: Sender<Box<dyn TaskHandle>>,
     cancel_tx: broadcast::Sender<()>,
+    watch_stream: Option<BoxStream<'static, Vec<u8>>>,
+    handlers: Vec<Handler>,
+}
+
+/// Where `Instruction::ReturnFn` resumes, and how many argument slots below
+/// the result it must pop, for one outstanding `Instruction::CallFn`. Kept
+/// entirely separate from `call_stack`, which only ever serves the
+/// parameterless `Call`/`Return` used by `scene`/`sequence`/`start`/`stop`.
+struct FnFrame {
+    return_ip: usize,
+    argc: usize,
 }
 
-impl<E: Engine> fmt::Debug for Thread<E> {
+/// Where to resume, and how much of the stack/call stack to unwind, if a
+/// `try` block's body fails before reaching its matching `PopHandler`.
+struct Handler {
+    catch_ip: usize,
+    stack_len: usize,
+    call_stack_len: usize,
+    fn_frames_len: usize,
+}
+
+impl<E: Engine, S: Spawner> fmt::Debug for Thread<E, S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Point")
             .field("ip", &self.ctx.ip)
-            .field("stack_ptr", &self.ctx.stack_ptr)
+            .field("stack_len", &self.ctx.stack.len())
             .finish()
     }
 }
@@ -72,26 +328,31 @@ enum StepResult {
     Break,
 }
 
-impl<E: Engine + 'static> Thread<E> {
+impl<E: Engine + 'static, S: Spawner + 'static> Thread<E, S> {
     fn new(
         engine: E,
+        spawner: S,
         code: Arc<Code>,
         ip: usize,
-        sender: Sender<JoinHandle<Result<()>>>,
-    ) -> Thread<E> {
+        sender: Sender<Box<dyn TaskHandle>>,
+    ) -> Thread<E, S> {
         let (cancel_tx, cancel_rx) = broadcast::channel(1);
         Thread {
             cancel_rx,
             ctx: ThreadContext {
-                id: Thread::<E>::next_id(),
+                id: Thread::<E, S>::next_id(),
                 engine,
+                spawner,
                 code,
                 ip,
-                stack: unsafe { std::mem::zeroed() },
-                stack_ptr: 0,
+                cur_ip: ip,
+                stack: Vec::with_capacity(STACK_SIZE),
                 call_stack: Vec::new(),
+                fn_frames: Vec::new(),
                 sender,
                 cancel_tx,
+                watch_stream: None,
+                handlers: Vec::new(),
             },
         }
     }
@@ -111,14 +372,27 @@ impl<E: Engine + 'static> Thread<E> {
                 // TODO: Restructure so that we do not have to pre-emptively resubsribe for each
                 // step
                 step = self.ctx.step(shutdown.resubscribe()) => {
-                    match step? {
-                        StepResult::Continue => {}
-                        StepResult::SceneContext => {
+                    match step {
+                        Ok(StepResult::Continue) => {}
+                        Ok(StepResult::SceneContext) => {
                             let (cancel_tx, cancel_rx) = broadcast::channel(1);
                             self.cancel_rx = cancel_rx;
                             self.ctx.cancel_tx = cancel_tx;
                         },
-                        StepResult::Break => break,
+                        Ok(StepResult::Break) => break,
+                        Err(err) => match self.ctx.handlers.pop() {
+                            // A `try` block is in scope: unwind to it instead
+                            // of letting the failure kill this thread, and
+                            // hand the handler the fault it raised.
+                            Some(handler) => {
+                                self.ctx.stack.truncate(handler.stack_len);
+                                self.ctx.call_stack.truncate(handler.call_stack_len);
+                                self.ctx.fn_frames.truncate(handler.fn_frames_len);
+                                self.ctx.push(Value::Fault(Fault::classify(&err)))?;
+                                self.ctx.ip = handler.catch_ip;
+                            }
+                            None => return Err(err),
+                        },
                     }
                 },
                 _ = shutdown.recv() => break,
@@ -128,72 +402,149 @@ impl<E: Engine + 'static> Thread<E> {
         Ok(())
     }
 }
-impl<E: Engine + 'static> ThreadContext<E> {
-    fn spawn(&self, ip: usize) -> Thread<E> {
+impl<E: Engine + 'static, S: Spawner + 'static> ThreadContext<E, S> {
+    fn spawn(&self, ip: usize) -> Thread<E, S> {
         let cancel_tx = self.cancel_tx.clone();
         let cancel_rx = self.cancel_tx.subscribe();
         Thread {
             ctx: ThreadContext {
-                id: Thread::<E>::next_id(),
+                id: Thread::<E, S>::next_id(),
                 engine: self.engine.clone(),
+                spawner: self.spawner.clone(),
                 code: self.code.clone(),
                 ip,
+                cur_ip: ip,
                 stack: self.stack.clone(),
-                stack_ptr: self.stack_ptr,
                 call_stack: Vec::new(),
+                fn_frames: Vec::new(),
                 sender: self.sender.clone(),
                 cancel_tx,
+                watch_stream: None,
+                handlers: Vec::new(),
             },
             cancel_rx,
         }
     }
-    pub fn pick(&mut self, depth: usize) {
-        self.push(self.stack[self.stack_ptr - 1 - depth].clone());
+    pub fn pick(&mut self, depth: usize) -> std::result::Result<(), VmError> {
+        let len = self.stack.len();
+        let index = len
+            .checked_sub(1 + depth)
+            .ok_or(VmError::StackUnderflow { ip: self.cur_ip })?;
+        self.push(self.stack[index].clone())
+    }
+
+    pub fn push(&mut self, value: Value) -> std::result::Result<(), VmError> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(VmError::StackOverflow { ip: self.cur_ip });
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> std::result::Result<Value, VmError> {
+        self.stack
+            .pop()
+            .ok_or(VmError::StackUnderflow { ip: self.cur_ip })
+    }
+
+    /// Pops a value expected to be a [`Value::Duration`], reporting a
+    /// [`VmError::TypeMismatch`] (rather than panicking) if it isn't.
+    fn pop_duration(&mut self) -> std::result::Result<Duration, VmError> {
+        let ip = self.cur_ip;
+        match self.pop()? {
+            Value::Duration(d) => Ok(d),
+            other => Err(VmError::TypeMismatch {
+                ip,
+                expected: "duration",
+                found: describe(&other),
+            }),
+        }
+    }
+
+    /// Pops a value expected to be a [`Value::Time`]. See [`Self::pop_duration`].
+    fn pop_time(&mut self) -> std::result::Result<TimeOfDay, VmError> {
+        let ip = self.cur_ip;
+        match self.pop()? {
+            Value::Time(t) => Ok(t),
+            other => Err(VmError::TypeMismatch {
+                ip,
+                expected: "time",
+                found: describe(&other),
+            }),
+        }
+    }
+
+    /// Pops a value expected to be a [`Value::Bool`]. See [`Self::pop_duration`].
+    fn pop_bool(&mut self) -> std::result::Result<bool, VmError> {
+        let ip = self.cur_ip;
+        match self.pop()? {
+            Value::Bool(b) => Ok(b),
+            other => Err(VmError::TypeMismatch {
+                ip,
+                expected: "bool",
+                found: describe(&other),
+            }),
+        }
     }
 
-    pub fn push(&mut self, value: Value) {
-        self.stack[self.stack_ptr] = value;
-        self.stack_ptr += 1; // ignoring the potential stack overflow
+    /// Pops a value expected to be a [`Value::Jump`] (an `Instruction::Call`/
+    /// `CallFn` target). See [`Self::pop_duration`].
+    fn pop_jump(&mut self) -> std::result::Result<usize, VmError> {
+        let ip = self.cur_ip;
+        match self.pop()? {
+            Value::Jump(target) => Ok(target),
+            other => Err(VmError::TypeMismatch {
+                ip,
+                expected: "jump target",
+                found: describe(&other),
+            }),
+        }
     }
 
-    pub fn pop(&mut self) -> Value {
-        // ignoring the potential of stack underflow
-        // cloning rather than mem::replace for easier testing
-        let v = self.stack[self.stack_ptr - 1].clone();
-        self.stack_ptr -= 1;
-        v
+    /// Pops a value expected to be a [`Value::Integer`]. See [`Self::pop_duration`].
+    fn pop_integer(&mut self) -> std::result::Result<i64, VmError> {
+        let ip = self.cur_ip;
+        match self.pop()? {
+            Value::Integer(i) => Ok(i),
+            other => Err(VmError::TypeMismatch {
+                ip,
+                expected: "integer",
+                found: describe(&other),
+            }),
+        }
     }
 
     async fn step(&mut self, shutdown: broadcast::Receiver<()>) -> Result<StepResult> {
         let inst_addr = self.ip;
         self.ip += 1;
+        self.cur_ip = inst_addr;
 
         log::debug!("inst[{}]: {:?}", self.id, self.code.instructions[inst_addr]);
         match self.code.instructions[inst_addr] {
             Instruction::Constant(const_idx) => {
-                self.push(self.code.constants[const_idx].clone());
+                self.push(self.code.constants[const_idx].clone())?;
             }
             Instruction::Print => {
-                let msg = format!("{}", self.pop());
+                let msg = format!("{}", self.pop()?);
                 self.engine.print(msg.as_str()).await?;
             }
             Instruction::Pick(depth) => {
-                self.pick(depth);
+                self.pick(depth)?;
             }
             Instruction::Pop => {
-                self.pop();
+                self.pop()?;
             }
             Instruction::Swap => {
-                let a = self.pop();
-                let b = self.pop();
-                self.push(a);
-                self.push(b);
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.push(a)?;
+                self.push(b)?;
             }
             Instruction::Spawn(ip) => {
                 let new_thread = self.spawn(self.ip);
-                let join_handle = tokio::spawn(new_thread.run(shutdown));
+                let task_handle = self.spawner.spawn(new_thread.run(shutdown));
                 // Track every spawned thread, so we can join on them
-                self.sender.send(join_handle).await?;
+                self.sender.send(task_handle).await?;
 
                 // update local ip to jump location
                 self.ip = ip;
@@ -207,38 +558,70 @@ impl<E: Engine + 'static> ThreadContext<E> {
                 return Ok(StepResult::Break);
             }
             Instruction::Get => {
-                let path: String = self.pop().try_into()?;
+                let path: String = self.pop()?.try_into()?;
                 // Creature future and queue it for the executor
                 let value = self.engine.get(path.as_str()).await?;
-                self.push(value[..].try_into()?);
+                self.push(value[..].try_into()?)?;
             }
             Instruction::Set => {
-                let value: Vec<u8> = self.pop().try_into()?;
-                let path: String = self.pop().try_into()?;
+                let value: Vec<u8> = self.pop()?.try_into()?;
+                let path: String = self.pop()?.try_into()?;
                 // Creature future and queue it for the executor
                 self.engine.set(path.as_str(), value).await?;
             }
+            Instruction::Watch => {
+                let path: String = self.pop()?.try_into()?;
+                self.watch_stream = Some(self.engine.watch(path.as_str()).await?);
+            }
+            Instruction::CountWatch => {
+                let window = self.pop_duration()?;
+                let path: String = self.pop()?.try_into()?;
+                self.watch_stream = Some(self.engine.watch_count(path.as_str(), window).await?);
+            }
+            Instruction::Await => {
+                let stream = self
+                    .watch_stream
+                    .as_mut()
+                    .expect("Await without a preceding Watch");
+                match stream.next().await {
+                    Some(value) => self.push(value[..].try_into()?)?,
+                    None => return Ok(StepResult::Break),
+                }
+            }
             Instruction::Wait => {
-                let v = self.pop();
-                match v {
-                    Value::Duration(d) => {
-                        self.engine.wait(d).await?;
-                    }
-                    _ => {
-                        panic!("wait arg must be a duration")
-                    }
-                };
+                let d = self.pop_duration()?;
+                self.engine.wait(d).await?;
             }
             Instruction::Call => {
                 self.call_stack.push(self.ip);
-                self.ip = match self.pop() {
-                    Value::Jump(ip) => ip,
-                    _ => panic!("call pointer not a jump value"),
-                };
+                self.ip = self.pop_jump()?;
             }
             Instruction::Return => {
                 self.ip = self.call_stack.pop().unwrap();
             }
+            Instruction::CallFn(argc) => {
+                if self.fn_frames.len() >= DEFAULT_CALL_STACK_LIMIT {
+                    return Err(anyhow::anyhow!(
+                        "call stack depth exceeded limit of {} (possible unbounded recursion)",
+                        DEFAULT_CALL_STACK_LIMIT
+                    ));
+                }
+                let target = self.pop_jump()?;
+                self.fn_frames.push(FnFrame {
+                    return_ip: self.ip,
+                    argc,
+                });
+                self.ip = target;
+            }
+            Instruction::ReturnFn => {
+                let frame = self.fn_frames.pop().expect("ReturnFn without a CallFn");
+                let result = self.pop()?;
+                for _ in 0..frame.argc {
+                    self.pop()?;
+                }
+                self.push(result)?;
+                self.ip = frame.return_ip;
+            }
             Instruction::SceneContext => {
                 return Ok(StepResult::SceneContext);
             }
@@ -247,60 +630,304 @@ impl<E: Engine + 'static> ThreadContext<E> {
                 log::debug!("stopped {} scene threads", count);
             }
             Instruction::At => {
-                let v = self.pop();
-                match v {
-                    Value::Time(t) => {
-                        let then: DateTime<Local> = match t {
-                            TimeOfDay::Sunrise => todo!(),
-                            TimeOfDay::Sunset => todo!(),
-                            TimeOfDay::HM(h, m) => Local::today().and_hms(h, m, 0),
-                        };
-                        let now: DateTime<Local> = Local::now();
-                        let mut diff = then.timestamp() - now.timestamp();
-                        if diff <= 0 {
-                            // If the time has passed today wait for the next one.
-                            diff += 24 * 60 * 60;
-                        }
-                        let d = Duration::from_secs(diff as u64);
-                        self.engine.wait(d).await?;
+                let t = self.pop_time()?;
+                let (lat, lon) = self.engine.location();
+                let tz = self.engine.timezone();
+                let now: DateTime<Local> = self.engine.now().await;
+                let then: DateTime<Local> = match t {
+                    TimeOfDay::Sunrise => {
+                        crate::sun::next_sunrise(lat, lon, tz, now).ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "sun does not rise at this location within the next year"
+                            )
+                        })?
+                    }
+                    TimeOfDay::Sunset => {
+                        crate::sun::next_sunset(lat, lon, tz, now).ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "sun does not set at this location within the next year"
+                            )
+                        })?
+                    }
+                    TimeOfDay::Dawn => {
+                        crate::sun::next_dawn(lat, lon, tz, now).ok_or_else(|| {
+                            anyhow::anyhow!("no civil dawn at this location within the next year")
+                        })?
                     }
-                    _ => {
-                        panic!("at arg must be a time")
+                    TimeOfDay::Dusk => {
+                        crate::sun::next_dusk(lat, lon, tz, now).ok_or_else(|| {
+                            anyhow::anyhow!("no civil dusk at this location within the next year")
+                        })?
                     }
+                    TimeOfDay::SolarNoon => crate::sun::next_solar_noon(lat, lon, tz, now),
+                    TimeOfDay::HM(h, m) => crate::sun::next_clock_time(tz, h, m, now),
                 };
+                // `then` is always strictly after `now` by
+                // construction (see `sun::next_event`/
+                // `sun::next_clock_time`), so this never underflows.
+                let d = (then - now).to_std().unwrap_or(Duration::from_secs(0));
+                self.engine.wait(d).await?;
             }
             Instruction::Equal => {
-                let rhs = self.pop();
-                let lhs = self.pop();
-                self.push(Value::Bool(rhs == lhs))
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(Value::Bool(rhs == lhs))?
             }
-            Instruction::JmpNot(ip) => {
-                let v = self.pop();
-                match v {
-                    Value::Bool(true) => {
-                        // Do not jump
+            Instruction::NotEqual => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(Value::Bool(rhs != lhs))?
+            }
+            Instruction::LessThan => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(Value::Bool(
+                    ordered(lhs, rhs, ip)? == std::cmp::Ordering::Less,
+                ))?;
+            }
+            Instruction::GreaterThan => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(Value::Bool(
+                    ordered(lhs, rhs, ip)? == std::cmp::Ordering::Greater,
+                ))?;
+            }
+            Instruction::LessEqual => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(Value::Bool(
+                    ordered(lhs, rhs, ip)? != std::cmp::Ordering::Greater,
+                ))?;
+            }
+            Instruction::GreaterEqual => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(Value::Bool(
+                    ordered(lhs, rhs, ip)? != std::cmp::Ordering::Less,
+                ))?;
+            }
+            Instruction::Add => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = match (lhs, rhs) {
+                    (Value::Integer(l), Value::Integer(r)) => {
+                        Value::Integer(l.checked_add(r).ok_or(VmError::Overflow { ip })?)
+                    }
+                    (Value::Float(l), Value::Float(r)) => Value::Float(l + r),
+                    (Value::Integer(l), Value::Float(r)) => Value::Float(l as f64 + r),
+                    (Value::Float(l), Value::Integer(r)) => Value::Float(l + r as f64),
+                    (Value::Duration(l), Value::Duration(r)) => {
+                        Value::Duration(l.checked_add(r).ok_or(VmError::Overflow { ip })?)
+                    }
+                    (a, b) => {
+                        return Err(VmError::TypeMismatch {
+                            ip,
+                            expected: "number or duration",
+                            found: if is_numeric_or_duration(&a) {
+                                describe(&b)
+                            } else {
+                                describe(&a)
+                            },
+                        }
+                        .into());
+                    }
+                };
+                self.push(result)?;
+            }
+            Instruction::Sub => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = match (lhs, rhs) {
+                    (Value::Integer(l), Value::Integer(r)) => {
+                        Value::Integer(l.checked_sub(r).ok_or(VmError::Overflow { ip })?)
                     }
-                    Value::Bool(false) => {
-                        self.ip = ip;
+                    (Value::Float(l), Value::Float(r)) => Value::Float(l - r),
+                    (Value::Integer(l), Value::Float(r)) => Value::Float(l as f64 - r),
+                    (Value::Float(l), Value::Integer(r)) => Value::Float(l - r as f64),
+                    (Value::Duration(l), Value::Duration(r)) => {
+                        Value::Duration(l.checked_sub(r).ok_or(VmError::Overflow { ip })?)
                     }
-                    _ => {
-                        panic!("value must be a bool")
+                    (a, b) => {
+                        return Err(VmError::TypeMismatch {
+                            ip,
+                            expected: "number or duration",
+                            found: if is_numeric_or_duration(&a) {
+                                describe(&b)
+                            } else {
+                                describe(&a)
+                            },
+                        }
+                        .into());
+                    }
+                };
+                self.push(result)?;
+            }
+            Instruction::Mul => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = match (lhs, rhs) {
+                    (Value::Integer(l), Value::Integer(r)) => {
+                        Value::Integer(l.checked_mul(r).ok_or(VmError::Overflow { ip })?)
+                    }
+                    (Value::Float(l), Value::Float(r)) => Value::Float(l * r),
+                    (Value::Integer(l), Value::Float(r)) => Value::Float(l as f64 * r),
+                    (Value::Float(l), Value::Integer(r)) => Value::Float(l * r as f64),
+                    (Value::Duration(l), Value::Integer(r)) => {
+                        let factor = u32::try_from(r).map_err(|_| VmError::Overflow { ip })?;
+                        Value::Duration(l.checked_mul(factor).ok_or(VmError::Overflow { ip })?)
+                    }
+                    (Value::Integer(l), Value::Duration(r)) => {
+                        let factor = u32::try_from(l).map_err(|_| VmError::Overflow { ip })?;
+                        Value::Duration(r.checked_mul(factor).ok_or(VmError::Overflow { ip })?)
+                    }
+                    (a, b) => {
+                        return Err(VmError::TypeMismatch {
+                            ip,
+                            expected: "number, or a duration and an integer",
+                            found: if is_numeric_or_duration(&a) {
+                                describe(&b)
+                            } else {
+                                describe(&a)
+                            },
+                        }
+                        .into());
+                    }
+                };
+                self.push(result)?;
+            }
+            Instruction::Div => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = match (lhs, rhs) {
+                    (Value::Integer(_), Value::Integer(0)) => {
+                        return Err(VmError::DivideByZero { ip }.into());
+                    }
+                    (Value::Integer(l), Value::Integer(r)) => {
+                        Value::Integer(l.checked_div(r).ok_or(VmError::Overflow { ip })?)
+                    }
+                    (Value::Float(l), Value::Float(r)) => Value::Float(l / r),
+                    (Value::Integer(l), Value::Float(r)) => Value::Float(l as f64 / r),
+                    (Value::Float(l), Value::Integer(r)) => Value::Float(l / r as f64),
+                    (Value::Duration(_), Value::Integer(0)) => {
+                        return Err(VmError::DivideByZero { ip }.into());
+                    }
+                    (Value::Duration(l), Value::Integer(r)) => {
+                        let divisor = u32::try_from(r).map_err(|_| VmError::Overflow { ip })?;
+                        Value::Duration(l / divisor)
+                    }
+                    (a, b) => {
+                        return Err(VmError::TypeMismatch {
+                            ip,
+                            expected: "number, or a duration and an integer",
+                            found: if is_numeric_or_duration(&a) {
+                                describe(&b)
+                            } else {
+                                describe(&a)
+                            },
+                        }
+                        .into());
                     }
+                };
+                self.push(result)?;
+            }
+            Instruction::Mod => {
+                let ip = self.cur_ip;
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = match (lhs, rhs) {
+                    (Value::Integer(_), Value::Integer(0)) => {
+                        return Err(VmError::DivideByZero { ip }.into());
+                    }
+                    (Value::Integer(l), Value::Integer(r)) => Value::Integer(l % r),
+                    (Value::Float(l), Value::Float(r)) => Value::Float(l % r),
+                    (Value::Integer(l), Value::Float(r)) => Value::Float(l as f64 % r),
+                    (Value::Float(l), Value::Integer(r)) => Value::Float(l % r as f64),
+                    (a, b) => {
+                        return Err(VmError::TypeMismatch {
+                            ip,
+                            expected: "integer or float",
+                            found: if matches!(a, Value::Integer(_) | Value::Float(_)) {
+                                describe(&b)
+                            } else {
+                                describe(&a)
+                            },
+                        }
+                        .into());
+                    }
+                };
+                self.push(result)?;
+            }
+            Instruction::JmpNot(ip) => {
+                if !self.pop_bool()? {
+                    self.ip = ip;
                 }
             }
+            Instruction::PushHandler(catch_ip) => {
+                self.handlers.push(Handler {
+                    catch_ip,
+                    stack_len: self.stack.len(),
+                    call_stack_len: self.call_stack.len(),
+                    fn_frames_len: self.fn_frames.len(),
+                });
+            }
+            Instruction::PopHandler => {
+                self.handlers.pop();
+            }
             Instruction::Index => {
-                if let Value::Str(prop) = self.pop() {
-                    if let Value::Object(props) = self.pop() {
-                        if let Some(v) = props.get(&prop) {
-                            self.push(v.to_owned());
-                        } else {
-                            panic!("object does not have property")
+                let Value::Str(prop) = self.pop()? else {
+                    panic!("index property must be a string value");
+                };
+                match self.pop()? {
+                    Value::Object(props) => match props.get(&prop) {
+                        Some(v) => self.push(v.to_owned())?,
+                        None => panic!("object does not have property '{prop}'"),
+                    },
+                    // There's no array-index syntax in the grammar yet, so
+                    // `obj.0`'s `prop` arrives the same way `obj.name` does:
+                    // as a string, just one that happens to parse as an
+                    // index.
+                    Value::Array(items) => {
+                        let ip = self.cur_ip;
+                        let index: usize = prop.parse().map_err(|_| VmError::TypeMismatch {
+                            ip,
+                            expected: "array index (a non-negative integer)",
+                            found: "non-numeric string",
+                        })?;
+                        match items.get(index) {
+                            Some(v) => self.push(v.to_owned())?,
+                            None => {
+                                return Err(VmError::IndexOutOfRange {
+                                    ip,
+                                    index,
+                                    len: items.len(),
+                                }
+                                .into());
+                            }
                         }
-                    } else {
-                        panic!("cannot index into non object values")
                     }
-                } else {
-                    panic!("index property must be a string value")
+                    _ => panic!("cannot index into non object/array values"),
+                }
+            }
+            Instruction::Now => {
+                let now: DateTime<Local> = self.engine.now().await;
+                self.push(Value::Integer(now.timestamp()))?;
+            }
+            Instruction::WaitUntil => {
+                let offset = self.pop_duration()?;
+                let anchor = self.pop_integer()?;
+                let target = anchor + offset.as_secs() as i64;
+                let now: DateTime<Local> = self.engine.now().await;
+                let diff = target - now.timestamp();
+                if diff > 0 {
+                    self.engine.wait(Duration::from_secs(diff as u64)).await?;
                 }
             }
         };
@@ -308,43 +935,241 @@ impl<E: Engine + 'static> ThreadContext<E> {
     }
 }
 
-pub struct VM<E: Engine> {
+pub struct VM<E: Engine, S: Spawner = TokioSpawner> {
     engine: E,
+    spawner: S,
+}
+impl<E: Engine + 'static> VM<E, TokioSpawner> {
+    /// Builds a `VM` that spawns threads onto the ambient tokio runtime.
+    pub fn new(engine: E) -> VM<E, TokioSpawner> {
+        VM {
+            engine,
+            spawner: TokioSpawner,
+        }
+    }
 }
-impl<E: Engine + 'static> VM<E> {
-    pub fn new(engine: E) -> VM<E> {
-        VM { engine }
+impl<E: Engine + 'static, S: Spawner + 'static> VM<E, S> {
+    /// Builds a `VM` that spawns threads through a custom [`Spawner`], so the
+    /// VM can be embedded in a host that isn't running tokio.
+    pub fn with_spawner(engine: E, spawner: S) -> VM<E, S> {
+        VM { engine, spawner }
+    }
+
+    /// Builds a `VM` whose spawned threads are batched into fixed
+    /// `throttling_interval` quanta rather than scheduled the instant each
+    /// becomes ready. Off by default (see [`VM::new`]); opt in when many
+    /// concurrent `wait`/`at` timers would otherwise cause a wakeup storm.
+    pub fn with_throttling(
+        engine: E,
+        spawner: S,
+        throttling_interval: Duration,
+    ) -> VM<E, ThrottlingSpawner<S>> {
+        VM {
+            engine,
+            spawner: ThrottlingSpawner::new(spawner, throttling_interval),
+        }
     }
     pub async fn run(&self, code: Code, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
         // Create channel for thread join handles
         let (thread_join_send, mut thread_join_recv) = mpsc::channel(100);
 
+        // All spawned threads actually listen on this internal channel rather
+        // than the caller's `shutdown` directly, so that the first `Err` from
+        // any thread can cancel every sibling the same way an external
+        // shutdown would. A small forwarding task relays the caller's signal
+        // onto it.
+        let (abort_tx, _) = broadcast::channel(1);
+        {
+            let abort_tx = abort_tx.clone();
+            let mut shutdown = shutdown.resubscribe();
+            tokio::spawn(async move {
+                let _ = shutdown.recv().await;
+                let _ = abort_tx.send(());
+            });
+        }
+
+        let code = Arc::new(code);
+
+        // Run the `startup` scene, if the program declared one, once before
+        // anything else, so devices start from a known state rather than
+        // whatever they happened to be left in.
+        if let Some(ip) = code.startup {
+            let trampoline = Thread::new(
+                self.engine.clone(),
+                self.spawner.clone(),
+                code.clone(),
+                ip,
+                thread_join_send.clone(),
+            );
+            if let Err(err) = trampoline.run(abort_tx.subscribe()).await {
+                let _ = abort_tx.send(());
+                return Err(err);
+            }
+        }
+
         // Create and run main thread
-        let thread = Thread::new(self.engine.clone(), Arc::new(code), 0, thread_join_send);
-        thread.run(shutdown.resubscribe()).await?;
+        let thread = Thread::new(
+            self.engine.clone(),
+            self.spawner.clone(),
+            code.clone(),
+            0,
+            thread_join_send.clone(),
+        );
+        let mut first_err = thread.run(abort_tx.subscribe()).await.err();
+        if first_err.is_some() {
+            let _ = abort_tx.send(());
+        }
 
         // Now that the main thread is completed wait until all other threads
-        // are completed before returning.
+        // are completed before returning. The first sibling to fail aborts
+        // every other outstanding thread via `abort_tx` and becomes the
+        // error `run` ultimately returns.
         //
-        // NOTE: The thread_join_send, will be dropped once all active threads are
-        // completed and this loop will terminate.
-        loop {
+        // `joins` holds the join future of every `when`/`wait`/`at` thread
+        // still outstanding. Every time it drains to empty (including right
+        // here, if the program never spawned any), the `idle` scene, if the
+        // program declared one, is (re)run, giving devices a guaranteed
+        // default state once transient automations finish rather than
+        // leaving them in whatever state the last `set` left them.
+        //
+        // `idle` is itself invoked through `thread_join_send`, so `run()`
+        // must keep a clone of it alive for as long as the program is
+        // running; unlike the rest of the VM this means the channel never
+        // closes on its own, so completion is driven entirely by `joins`
+        // going (and staying) empty rather than by the channel closing.
+        let mut joins: FuturesUnordered<BoxFuture<'static, Result<()>>> = FuturesUnordered::new();
+        Self::drain_joins(&mut thread_join_recv, &mut joins);
+
+        let mut idle_ran = false;
+        if joins.is_empty() && !idle_ran && first_err.is_none() {
+            idle_ran = true;
+            if let Err(err) = Self::run_idle(
+                &code,
+                &self.engine,
+                &self.spawner,
+                &thread_join_send,
+                abort_tx.subscribe(),
+            )
+            .await
+            {
+                let _ = abort_tx.send(());
+                first_err = Some(err);
+            }
+            Self::drain_joins(&mut thread_join_recv, &mut joins);
+        }
+
+        while first_err.is_none() && !(joins.is_empty() && idle_ran) {
             select! {
                 thread_join = thread_join_recv.recv() => {
-                    if let Some(thread_join) = thread_join {
-                        select! {
-                        _ = thread_join => {},
-                        _ = shutdown.recv() => break,
-                        };
-                    } else {
-                        // All threads have completed
-                        break;
+                    if let Some(task_handle) = thread_join {
+                        idle_ran = false;
+                        joins.push(task_handle.join());
+                    }
+                }
+                Some(result) = joins.next(), if !joins.is_empty() => {
+                    if let Err(err) = result {
+                        if first_err.is_none() {
+                            let _ = abort_tx.send(());
+                            first_err = Some(classify_join_error(err));
+                        }
+                    }
+                    if joins.is_empty() && !idle_ran && first_err.is_none() {
+                        idle_ran = true;
+                        if let Err(err) = Self::run_idle(
+                            &code,
+                            &self.engine,
+                            &self.spawner,
+                            &thread_join_send,
+                            abort_tx.subscribe(),
+                        )
+                        .await
+                        {
+                            let _ = abort_tx.send(());
+                            first_err = Some(err);
+                        }
+                        Self::drain_joins(&mut thread_join_recv, &mut joins);
                     }
                 }
                 _ = shutdown.recv() => break,
             }
         }
-        Ok(())
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves every join future already buffered in the channel into `joins`
+    /// without blocking, so callers can tell whether anything is
+    /// outstanding right now rather than only finding out the next time the
+    /// select loop happens to poll the channel.
+    fn drain_joins(
+        thread_join_recv: &mut mpsc::Receiver<Box<dyn TaskHandle>>,
+        joins: &mut FuturesUnordered<BoxFuture<'static, Result<()>>>,
+    ) {
+        while let Ok(task_handle) = thread_join_recv.try_recv() {
+            joins.push(task_handle.join());
+        }
+    }
+
+    /// Runs the program's `idle` scene to completion, if it declared one.
+    async fn run_idle(
+        code: &Arc<Code>,
+        engine: &E,
+        spawner: &S,
+        thread_join_send: &Sender<Box<dyn TaskHandle>>,
+        abort: broadcast::Receiver<()>,
+    ) -> Result<()> {
+        match code.idle {
+            Some(ip) => {
+                let thread = Thread::new(
+                    engine.clone(),
+                    spawner.clone(),
+                    code.clone(),
+                    ip,
+                    thread_join_send.clone(),
+                );
+                thread.run(abort).await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Why a spawned thread's join outcome resolved the way it did. Lets
+/// embedders tell whether a scene ended normally, was stopped, or aborted on
+/// failure, rather than seeing an opaque `anyhow::Error`.
+#[derive(Debug)]
+pub enum JoinError {
+    /// The thread's own code returned an error (e.g. a failed `engine.set`).
+    Failed(anyhow::Error),
+    /// The thread panicked instead of returning.
+    Panicked(String),
+    /// The thread was cancelled before it could complete.
+    Cancelled,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Failed(err) => write!(f, "thread failed: {err}"),
+            JoinError::Panicked(msg) => write!(f, "thread panicked: {msg}"),
+            JoinError::Cancelled => write!(f, "thread was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// Turns the opaque error surfaced by a [`crate::spawner::TaskHandle::join`]
+/// into a [`JoinError`], recovering the panic/cancellation distinction when
+/// the underlying executor is the default tokio one.
+fn classify_join_error(err: anyhow::Error) -> anyhow::Error {
+    match err.downcast::<tokio::task::JoinError>() {
+        Ok(join_err) if join_err.is_cancelled() => JoinError::Cancelled.into(),
+        Ok(join_err) if join_err.is_panic() => JoinError::Panicked(join_err.to_string()).into(),
+        Ok(join_err) => JoinError::Failed(join_err.into()).into(),
+        Err(err) => JoinError::Failed(err).into(),
     }
 }
 
@@ -358,8 +1183,10 @@ mod tests {
         },
         task::Poll,
     };
+    use tokio::sync::oneshot;
 
     use super::*;
+    use crate::ast;
     use crate::compiler::Interpreter;
     use crate::Compile;
 
@@ -514,6 +1341,50 @@ mod tests {
         let _ = shutdown.send(());
     }
     #[tokio::test]
+    async fn test_block_expr() {
+        // There's no block-expression syntax in this tree's grammar yet
+        // (see compiler::tests::test_block_expr), so this builds the AST
+        // directly instead of going through `Interpreter::from_source`:
+        // `let y = { let x = 1; x };` then `print y;`, proving `y`'s value
+        // survives the block's own scope-cleanup pops.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![
+            ast::Stmt::spanned(ast::StmtKind::Let(
+                "y".to_string(),
+                ast::Expr::spanned(ast::ExprKind::Block(vec![
+                    ast::Stmt::spanned(ast::StmtKind::Let(
+                        "x".to_string(),
+                        ast::Expr::spanned(ast::ExprKind::Integer(1)),
+                    )),
+                    ast::Stmt::spanned(ast::StmtKind::Expr(ast::Expr::spanned(
+                        ast::ExprKind::Ident("x".to_string()),
+                    ))),
+                ])),
+            )),
+            ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                ast::ExprKind::Ident("y".to_string()),
+            ))),
+        ]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            vec!["1".to_string()],
+            te.print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown_tx.send(());
+    }
+    #[tokio::test]
     async fn test_index() {
         let source = "
         let o = {x: 1};
@@ -650,20 +1521,1059 @@ mod tests {
         );
         let _ = shutdown.send(());
     }
+
+    struct FailingEngine;
+
+    #[async_trait]
+    impl Engine for FailingEngine {
+        async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+            empty().await
+        }
+        async fn set(&self, _path: &str, _value: Vec<u8>) -> Result<()> {
+            Err(anyhow::anyhow!("engine.set is unavailable"))
+        }
+    }
+    impl Clone for FailingEngine {
+        fn clone(&self) -> Self {
+            FailingEngine
+        }
+    }
+
     #[tokio::test]
-    async fn test_scene() {
+    async fn test_sibling_cancellation_on_error() {
         let source = "
-        scene night { print \"x\"; };
-        start night;
-        stop night;
+            wait 1h print \"never\";
+            set [path/to/value] \"on\";
     ";
-        let (te, shutdown) = run_vm(source);
-        // TODO: remove this sleep
-        time::sleep(Duration::from_millis(100)).await;
+        let code = Interpreter::from_source(source).unwrap();
+        let vm = VM::new(FailingEngine);
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
 
-        assert_eq!(0, te.get_count.load(Ordering::SeqCst));
-        assert_eq!(0, te.set_count.load(Ordering::SeqCst));
-        assert_eq!(0, te.wait_count.load(Ordering::SeqCst));
-        let _ = shutdown.send(());
+        // The `wait` spawns a sibling thread before the main thread's `set`
+        // fails; the failure must cancel that sibling and surface as the
+        // error `run` returns, instead of the sibling running forever while
+        // the failure is silently dropped.
+        let result =
+            tokio::time::timeout(Duration::from_millis(200), vm.run(code, shutdown_rx)).await;
+        let err = result
+            .expect("run should not hang waiting on the cancelled sibling")
+            .expect_err("a failing set should surface as an error");
+        assert!(err.to_string().contains("engine.set is unavailable"));
+    }
+
+    /// An engine whose `set` always fails, so a `try`/`on error` handler has
+    /// something to catch. Unlike `FailingEngine`, `print` is instrumented so
+    /// the handler's recovery path is observable.
+    struct FaultEngine {
+        print_args: Mutex<Vec<String>>,
+    }
+    impl FaultEngine {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                print_args: Mutex::new(Vec::new()),
+            })
+        }
+    }
+    #[async_trait]
+    impl Engine for Arc<FaultEngine> {
+        async fn print(&self, msg: &str) -> Result<()> {
+            self.print_args.lock().unwrap().push(msg.to_string());
+            Ok(())
+        }
+        async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+            empty().await
+        }
+        async fn set(&self, _path: &str, _value: Vec<u8>) -> Result<()> {
+            Err(anyhow::anyhow!("engine.set is unavailable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_try_on_error() {
+        // `try`/`on error` has no grammar support in this tree yet (see
+        // compiler::tests::test_try), so this builds the AST directly
+        // instead of going through `Interpreter::from_source`.
+        let ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![
+            ast::Stmt::spanned(ast::StmtKind::Try(
+                Box::new(ast::Stmt::spanned(ast::StmtKind::Set(
+                    "path/to/value".to_string(),
+                    ast::Expr::spanned(ast::ExprKind::String("on".to_string())),
+                ))),
+                "fault".to_string(),
+                Box::new(ast::Stmt::spanned(ast::StmtKind::Print(
+                    ast::Expr::spanned(ast::ExprKind::Ident("fault".to_string())),
+                ))),
+            )),
+            ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                ast::ExprKind::String("continued".to_string()),
+            ))),
+        ]));
+        let code = Interpreter::from_ast(ast).unwrap();
+        let engine = FaultEngine::new();
+        let vm = VM::new(engine.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        vm.run(code, shutdown_rx)
+            .await
+            .expect("the handler should recover instead of aborting run");
+
+        assert_eq!(
+            vec!["Other".to_string(), "continued".to_string()],
+            engine
+                .print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+    }
+
+    /// A test engine backed by a virtual clock instead of real wall-clock
+    /// sleeps, so scene scheduling can be driven and asserted deterministically.
+    ///
+    /// `wait(d)` never calls `tokio::time::sleep`; it registers `now + d` as a
+    /// deadline alongside a `oneshot` waker and parks on the receiver. `advance`
+    /// moves the shared instant forward and resolves every deadline that is now
+    /// due, in deadline order, so callers control exactly when each `wait`
+    /// fires without depending on real time passing.
+    struct MockClock {
+        state: Mutex<MockClockState>,
+        print_args: Mutex<Vec<String>>,
+    }
+    struct MockClockState {
+        now: DateTime<Local>,
+        pending: Vec<(DateTime<Local>, oneshot::Sender<()>)>,
+    }
+    impl MockClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                state: Mutex::new(MockClockState {
+                    now: Local::now(),
+                    pending: Vec::new(),
+                }),
+                print_args: Mutex::new(Vec::new()),
+            })
+        }
+        /// Moves the virtual clock forward by `d` and fires every pending
+        /// `wait` whose deadline has now elapsed, in deadline order.
+        fn advance(&self, d: Duration) {
+            let mut state = self.state.lock().unwrap();
+            state.now += chrono::Duration::from_std(d).unwrap();
+            let now = state.now;
+            state.pending.sort_by_key(|(deadline, _)| *deadline);
+            let mut i = 0;
+            while i < state.pending.len() {
+                if state.pending[i].0 <= now {
+                    let (_, tx) = state.pending.remove(i);
+                    let _ = tx.send(());
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    }
+    #[async_trait]
+    impl Engine for Arc<MockClock> {
+        async fn print(&self, msg: &str) -> Result<()> {
+            self.print_args.lock().unwrap().push(msg.to_string());
+            Ok(())
+        }
+        async fn now(&self) -> DateTime<Local> {
+            self.state.lock().unwrap().now
+        }
+        async fn wait(&self, d: Duration) -> Result<()> {
+            let (tx, rx) = oneshot::channel();
+            {
+                let mut state = self.state.lock().unwrap();
+                let deadline = state.now + chrono::Duration::from_std(d).unwrap();
+                state.pending.push((deadline, tx));
+            }
+            rx.await?;
+            Ok(())
+        }
+        async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+            empty().await
+        }
+        async fn set(&self, _path: &str, _value: Vec<u8>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_clock_many_threads() {
+        let source = "
+            wait 5s print \"a\";
+            wait 4s print \"b\";
+            wait 3s print \"c\";
+            wait 2s print \"d\";
+            wait 1s print \"e\";
+    ";
+        let code = Interpreter::from_source(source).unwrap();
+        let clock = MockClock::new();
+        let vm = VM::new(clock.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(2);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+
+        // Let every spawned thread reach its `wait` and register a deadline
+        // before the clock moves; no real sleep is involved.
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+
+        // A single advance past every deadline fires them in deadline order,
+        // regardless of the order the threads were spawned in.
+        clock.advance(Duration::from_secs(5));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(
+            vec!["e", "d", "c", "b", "a"],
+            clock
+                .print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_sequence_anchor_drift_free() {
+        // `sequence` has no grammar support in this tree yet (see
+        // compiler::tests::test_sequence), so this builds the AST directly
+        // instead of going through `Interpreter::from_source`.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![
+            ast::Stmt::spanned(ast::StmtKind::Sequence(
+                "s".to_string(),
+                vec![
+                    (
+                        ast::Expr::spanned(ast::ExprKind::Duration("0s".to_string())),
+                        ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                            ast::ExprKind::String("a".to_string()),
+                        ))),
+                    ),
+                    (
+                        ast::Expr::spanned(ast::ExprKind::Duration("5s".to_string())),
+                        ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                            ast::ExprKind::String("b".to_string()),
+                        ))),
+                    ),
+                ],
+            )),
+            ast::Stmt::spanned(ast::StmtKind::Start("s".to_string())),
+        ]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let clock = MockClock::new();
+        let vm = VM::new(clock.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(2);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        // The `+0s` step has already reached its anchor-relative deadline by
+        // the time it runs, so it fires immediately without waiting.
+        assert_eq!(
+            vec!["a".to_string()],
+            clock
+                .print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+
+        // Advancing exactly 5s past the shared anchor — not 5s past when the
+        // first step happened to finish running — fires the second step.
+        clock.advance(Duration::from_secs(5));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            vec!["b".to_string()],
+            clock
+                .print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_scene() {
+        let source = "
+        scene night { print \"x\"; };
+        start night;
+        stop night;
+    ";
+        let (te, shutdown) = run_vm(source);
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(0, te.get_count.load(Ordering::SeqCst));
+        assert_eq!(0, te.set_count.load(Ordering::SeqCst));
+        assert_eq!(0, te.wait_count.load(Ordering::SeqCst));
+        let _ = shutdown.send(());
+    }
+
+    #[tokio::test]
+    async fn test_startup_scene() {
+        let source = "
+        scene startup { print \"booted\"; };
+    ";
+        let (te, shutdown) = run_vm(source);
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(1, te.print_count.load(Ordering::SeqCst));
+        assert_eq!(
+            vec!["booted".to_string()],
+            te.print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown.send(());
+    }
+
+    #[tokio::test]
+    async fn test_idle_scene() {
+        let source = "
+        scene idle { set [path/to/value] \"off\"; };
+        wait 1s print \"done\";
+    ";
+        let (te, shutdown) = run_vm(source);
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        // The `wait` thread has already finished (`TestEngine::wait` resolves
+        // immediately), so the program has gone quiescent and `idle` should
+        // have run exactly once.
+        assert_eq!(1, te.set_count.load(Ordering::SeqCst));
+        assert_eq!(
+            vec![("path/to/value".to_string(), "off".to_string())],
+            te.set_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<(String, String)>>(),
+        );
+        let _ = shutdown.send(());
+    }
+
+    /// An engine whose `watch` replays a fixed burst of arrivals and then
+    /// never yields again, so tests can assert on an exact running count
+    /// without depending on real time passing between messages.
+    struct CountEngine {
+        print_args: Mutex<Vec<String>>,
+    }
+    impl CountEngine {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                print_args: Mutex::new(Vec::new()),
+            })
+        }
+    }
+    #[async_trait]
+    impl Engine for Arc<CountEngine> {
+        async fn print(&self, msg: &str) -> Result<()> {
+            self.print_args.lock().unwrap().push(msg.to_string());
+            Ok(())
+        }
+        async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+            empty().await
+        }
+        async fn set(&self, _path: &str, _value: Vec<u8>) -> Result<()> {
+            Ok(())
+        }
+        async fn watch(&self, _path: &str) -> Result<BoxStream<'static, Vec<u8>>> {
+            Ok(Box::pin(
+                futures::stream::iter(vec![b"on".to_vec(), b"on".to_vec(), b"on".to_vec()])
+                    .chain(futures::stream::pending()),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_when_count() {
+        // `count(<path>, window)` has no grammar support in this tree yet
+        // (see compiler::tests::test_when_count), so this builds the AST
+        // directly instead of going through `Interpreter::from_source`.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::When(
+            ast::Expr::spanned(ast::ExprKind::Binary(
+                Box::new(ast::Expr::spanned(ast::ExprKind::Count(
+                    Box::new(ast::Expr::spanned(ast::ExprKind::Path("motion".to_string()))),
+                    Box::new(ast::Expr::spanned(ast::ExprKind::Duration(
+                        "3600s".to_string(),
+                    ))),
+                ))),
+                ast::BinaryOpcode::Eql,
+                Box::new(ast::Expr::spanned(ast::ExprKind::Integer(2))),
+            )),
+            Box::new(ast::Stmt::spanned(ast::StmtKind::Print(
+                ast::Expr::spanned(ast::ExprKind::String("tripped".to_string())),
+            ))),
+        ));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let engine = CountEngine::new();
+        let vm = VM::new(engine.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        // Exactly the second of the three replayed arrivals crosses the
+        // `is 2` threshold; the third leaves the count at 3, not 2, so it
+        // should not fire the guard again.
+        assert_eq!(
+            vec!["tripped".to_string()],
+            engine
+                .print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown_tx.send(());
+    }
+
+    /// An engine whose `watch` replays a fixed stream keyed by path, so a
+    /// test can drive two outstanding subscriptions independently instead of
+    /// sharing one global call counter the way [`TestEngine::get`] does.
+    struct MultiPathEngine {
+        print_args: Mutex<Vec<String>>,
+    }
+    impl MultiPathEngine {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                print_args: Mutex::new(Vec::new()),
+            })
+        }
+    }
+    #[async_trait]
+    impl Engine for Arc<MultiPathEngine> {
+        async fn print(&self, msg: &str) -> Result<()> {
+            self.print_args.lock().unwrap().push(msg.to_string());
+            Ok(())
+        }
+        async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+            empty().await
+        }
+        async fn set(&self, _path: &str, _value: Vec<u8>) -> Result<()> {
+            Ok(())
+        }
+        async fn watch(&self, path: &str) -> Result<BoxStream<'static, Vec<u8>>> {
+            let arrivals: Vec<Vec<u8>> = match path {
+                "a" => vec![b"\"no\"".to_vec(), b"\"yes\"".to_vec()],
+                "b" => vec![b"\"yes\"".to_vec()],
+                other => panic!("unexpected watch path {other}"),
+            };
+            Ok(Box::pin(
+                futures::stream::iter(arrivals).chain(futures::stream::pending()),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_when_guards_run_concurrently_on_separate_paths() {
+        // Each `when` spawns its own thread with its own `watch_stream` (see
+        // `ThreadContext::spawn`), so two guards parked on different paths
+        // must resolve independently rather than serializing on a single
+        // outstanding subscription.
+        let source = r#"
+        when <a> is "yes" print "a-ready";
+        when <b> is "yes" print "b-ready";
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        let engine = MultiPathEngine::new();
+        let vm = VM::new(engine.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        let mut printed = engine
+            .print_args
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect::<Vec<_>>();
+        printed.sort();
+        assert_eq!(vec!["a-ready".to_string(), "b-ready".to_string()], printed);
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_func_call() {
+        // `func`/call-expression syntax has no grammar support in this tree
+        // yet (see compiler::tests::test_func_call), so this builds the AST
+        // directly instead of going through `Interpreter::from_source`.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![
+            ast::Stmt::spanned(ast::StmtKind::Func(
+                "add".to_string(),
+                vec!["a".to_string(), "b".to_string()],
+                Box::new(ast::Stmt::spanned(ast::StmtKind::Return(
+                    ast::Expr::spanned(ast::ExprKind::Binary(
+                        Box::new(ast::Expr::spanned(ast::ExprKind::Ident("a".to_string()))),
+                        ast::BinaryOpcode::Add,
+                        Box::new(ast::Expr::spanned(ast::ExprKind::Ident("b".to_string()))),
+                    )),
+                ))),
+            )),
+            ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                ast::ExprKind::Call(
+                    "add".to_string(),
+                    vec![
+                        ast::Expr::spanned(ast::ExprKind::Integer(2)),
+                        ast::Expr::spanned(ast::ExprKind::Integer(3)),
+                    ],
+                ),
+            ))),
+        ]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(2);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            vec!["5".to_string()],
+            te.print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_func_implicit_return() {
+        // Same shape as `test_func_call` above, except "add"'s body is a
+        // bare expression statement with no `return` (see
+        // compiler::tests::test_func_implicit_return): its value should
+        // still come back from the call.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![
+            ast::Stmt::spanned(ast::StmtKind::Func(
+                "add".to_string(),
+                vec!["a".to_string(), "b".to_string()],
+                Box::new(ast::Stmt::spanned(ast::StmtKind::Expr(ast::Expr::spanned(
+                    ast::ExprKind::Binary(
+                        Box::new(ast::Expr::spanned(ast::ExprKind::Ident("a".to_string()))),
+                        ast::BinaryOpcode::Add,
+                        Box::new(ast::Expr::spanned(ast::ExprKind::Ident("b".to_string()))),
+                    ),
+                )))),
+            )),
+            ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                ast::ExprKind::Call(
+                    "add".to_string(),
+                    vec![
+                        ast::Expr::spanned(ast::ExprKind::Integer(2)),
+                        ast::Expr::spanned(ast::ExprKind::Integer(3)),
+                    ],
+                ),
+            ))),
+        ]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(2);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            vec!["5".to_string()],
+            te.print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_func_recursion_depth_limit() {
+        // Unbounded recursion in this tree has no grammar support either
+        // (see test_func_call above), so the self-recursive `func` is built
+        // directly as an AST.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![
+            ast::Stmt::spanned(ast::StmtKind::Func(
+                "recur".to_string(),
+                vec![],
+                Box::new(ast::Stmt::spanned(ast::StmtKind::Return(
+                    ast::Expr::spanned(ast::ExprKind::Call("recur".to_string(), vec![])),
+                ))),
+            )),
+            ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                ast::ExprKind::Call("recur".to_string(), vec![]),
+            ))),
+        ]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm.run(code, shutdown_rx).await.expect_err(
+            "unbounded recursion should be rejected instead of overflowing the host stack",
+        );
+        assert!(err.to_string().contains("call stack depth exceeded"));
+    }
+
+    #[tokio::test]
+    async fn test_operand_stack_overflow_reports_vm_error() {
+        // There's no grammar construct that pushes values without ever
+        // popping them, so the overflow has to be built directly as `Code`
+        // rather than compiled from source (see `dot::tests` for the same
+        // pattern).
+        let mut instructions = vec![Instruction::Constant(0); STACK_SIZE + 1];
+        instructions.push(Instruction::Term);
+        let code = Code {
+            instructions,
+            constants: vec![Value::Integer(1)],
+            ..Default::default()
+        };
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("pushing past STACK_SIZE should overflow instead of panicking");
+        assert!(err.to_string().contains("overflow"));
+    }
+
+    #[tokio::test]
+    async fn test_operand_stack_underflow_reports_vm_error() {
+        // Same reasoning as the overflow test above: popping an empty stack
+        // has no source-level trigger, so `Code` is built by hand.
+        let code = Code {
+            instructions: vec![Instruction::Pop, Instruction::Term],
+            ..Default::default()
+        };
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("popping an empty stack should underflow instead of panicking");
+        assert!(err.to_string().contains("underflow"));
+    }
+
+    #[tokio::test]
+    async fn test_wait_type_mismatch_reports_vm_error_instead_of_panicking() {
+        // `check::check` only classifies literal `Expr`s (see its own
+        // doc comment), so a `wait` on a value that's computed rather than
+        // written as a literal duration sails through semantic checking and
+        // used to panic inside `step` instead of surfacing a clean error.
+        let source = r#"
+        let d = 1;
+        wait d print "unreachable";
+"#;
+        let code = Interpreter::from_source(source).unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("wait on a non-duration value should report a type mismatch");
+        assert!(err.to_string().contains("expected duration"));
+    }
+
+    #[tokio::test]
+    async fn test_less_than_type_mismatch_reports_vm_error_instead_of_panicking() {
+        // `check` never type-checks ordering operands (see check::check's
+        // own doc comment), so `5s < 1` sails through and used to panic
+        // inside `ordered` instead of surfacing a clean error.
+        let code = Interpreter::from_source("print 5s < 1;").unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("comparing a duration and an integer should report a type mismatch");
+        assert!(err.to_string().contains("found integer"));
+    }
+
+    #[tokio::test]
+    async fn test_duration_sub_underflow_reports_vm_error_instead_of_panicking() {
+        // `fold_binary` only folds a `Sub` whose `Duration` operands can't
+        // underflow (see compiler::tests), so `30s - 90s` reaches the VM as
+        // a runtime op instead of being caught at compile time.
+        let code = Interpreter::from_source("print 30s - 90s;").unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("subtracting a larger duration should report an error, not panic");
+        assert!(err.to_string().contains("arithmetic overflow"));
+    }
+
+    #[tokio::test]
+    async fn test_integer_divide_by_zero_reports_vm_error_instead_of_panicking() {
+        // `fold_binary` only folds a `Div` whose divisor is non-zero (see
+        // compiler::tests), so `5 / 0` reaches the VM as a runtime op
+        // instead of being caught at compile time.
+        let code = Interpreter::from_source("print 5 / 0;").unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("dividing by zero should report an error instead of panicking");
+        assert!(err.to_string().contains("division by zero"));
+    }
+
+    #[tokio::test]
+    async fn test_integer_add_overflow_reports_vm_error_instead_of_panicking() {
+        // Built directly as `Code` (same pattern as `dot::tests`) rather
+        // than compiled from source, so `i64::MAX + 1` reaches the VM as a
+        // runtime `Add` regardless of whether the grammar's integer literal
+        // parsing accepts a 19-digit boundary value.
+        let code = Code {
+            instructions: vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::Add,
+                Instruction::Print,
+                Instruction::Term,
+            ],
+            constants: vec![Value::Integer(i64::MAX), Value::Integer(1)],
+            ..Default::default()
+        };
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("overflowing integer addition should report an error, not panic");
+        assert!(err.to_string().contains("arithmetic overflow"));
+    }
+
+    #[tokio::test]
+    async fn test_duration_mul_negative_integer_reports_vm_error_instead_of_panicking() {
+        // There's no negative integer literal syntax in the grammar, so the
+        // program is built directly as `Code` rather than compiled from
+        // source (same pattern as `dot::tests`). A negative factor used to
+        // be cast straight to `u32`, silently wrapping to a huge multiplier
+        // and overflow-panicking `Duration`'s `Mul`; it should now be
+        // rejected before it ever reaches that cast.
+        let code = Code {
+            instructions: vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::Mul,
+                Instruction::Print,
+                Instruction::Term,
+            ],
+            constants: vec![Value::Duration(Duration::from_secs(30)), Value::Integer(-1)],
+            ..Default::default()
+        };
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("multiplying a duration by a negative integer should report an error");
+        assert!(err.to_string().contains("arithmetic overflow"));
+    }
+
+    #[tokio::test]
+    async fn test_add_type_mismatch_reports_vm_error_instead_of_panicking() {
+        // `check` only type-checks `is`/`!=` operands (see check::check's
+        // own doc comment), never arithmetic ones, so `true + 1` sails
+        // through and used to panic inside `step` instead of surfacing a
+        // clean error.
+        let code = Interpreter::from_source("print true + 1;").unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("adding a bool and an integer should report a type mismatch");
+        assert!(err.to_string().contains("found bool"));
+    }
+
+    #[tokio::test]
+    async fn test_array_index_out_of_range_reports_vm_error_instead_of_panicking() {
+        // There's no array-index syntax in the grammar yet (see `Index`'s
+        // own comment), so the indexing program is built directly as
+        // `Code` rather than compiled from source (same pattern as
+        // `dot::tests`).
+        let code = Code {
+            instructions: vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::Index,
+                Instruction::Term,
+            ],
+            constants: vec![
+                Value::Array(vec![Value::Integer(1)]),
+                Value::Str("5".to_string()),
+            ],
+            ..Default::default()
+        };
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let err = vm
+            .run(code, shutdown_rx)
+            .await
+            .expect_err("an out-of-range array index should report an error, not panic");
+        assert!(err.to_string().contains("out of range"));
+    }
+
+    #[tokio::test]
+    async fn test_every() {
+        // `every` has no grammar support in this tree yet (see
+        // compiler::tests::test_every), so this builds the AST directly
+        // instead of going through `Interpreter::from_source`.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![ast::Stmt::spanned(
+            ast::StmtKind::Every(
+                ast::Expr::spanned(ast::ExprKind::Duration("1s".to_string())),
+                Box::new(ast::Stmt::spanned(ast::StmtKind::Print(
+                    ast::Expr::spanned(ast::ExprKind::String("tick".to_string())),
+                ))),
+            ),
+        )]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let (te, shutdown_tx) = {
+            let te = TestEngine::new();
+            let vm = VM::new(te.clone());
+            let (shutdown_tx, shutdown_rx) = broadcast::channel(2);
+            tokio::spawn(async move {
+                vm.run(code, shutdown_rx).await.unwrap();
+            });
+            (te, shutdown_tx)
+        };
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        // `TestEngine::wait` resolves immediately rather than after the
+        // requested duration, so the spawned thread re-arms and re-prints
+        // many times over; what `every` promises is that it keeps firing
+        // without being re-triggered by hand, not any particular count.
+        assert!(te.wait_count.load(Ordering::SeqCst) > 1);
+        assert!(te
+            .print_args
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|msg| msg == "tick"));
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_and_or_short_circuit() {
+        // `&&`/`||` have no grammar support in this tree yet (see
+        // compiler::tests::test_binary_comparison_and_logical), so this
+        // builds the AST directly instead of going through
+        // `Interpreter::from_source`. Each `rhs` divides by zero, which
+        // would fail the thread if ever evaluated; both print successfully only if
+        // `lhs` alone is enough to decide the result.
+        let divide_by_zero = || {
+            ast::Expr::spanned(ast::ExprKind::Binary(
+                Box::new(ast::Expr::spanned(ast::ExprKind::Integer(1))),
+                ast::BinaryOpcode::Div,
+                Box::new(ast::Expr::spanned(ast::ExprKind::Integer(0))),
+            ))
+        };
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Block(vec![
+            ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                ast::ExprKind::Binary(
+                    Box::new(ast::Expr::spanned(ast::ExprKind::Boolean(false))),
+                    ast::BinaryOpcode::And,
+                    Box::new(divide_by_zero()),
+                ),
+            ))),
+            ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+                ast::ExprKind::Binary(
+                    Box::new(ast::Expr::spanned(ast::ExprKind::Boolean(true))),
+                    ast::BinaryOpcode::Or,
+                    Box::new(divide_by_zero()),
+                ),
+            ))),
+        ]));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            vec!["false".to_string(), "true".to_string()],
+            te.print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown_tx.send(());
+    }
+
+    struct ArrayEngine {
+        print_args: Mutex<Vec<String>>,
+    }
+    impl ArrayEngine {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                print_args: Mutex::new(Vec::new()),
+            })
+        }
+    }
+    #[async_trait]
+    impl Engine for Arc<ArrayEngine> {
+        async fn print(&self, msg: &str) -> Result<()> {
+            self.print_args.lock().unwrap().push(msg.to_string());
+            Ok(())
+        }
+        async fn get(&self, _path: &str) -> Result<Vec<u8>> {
+            future::ready(Ok(b"[10, 20, 30]".to_vec())).await
+        }
+        async fn set(&self, _path: &str, _value: Vec<u8>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_index_array() {
+        // There's no array-literal syntax in this tree's grammar yet (see
+        // compiler::tests::test_array_index), so this builds the AST
+        // directly instead of going through `Interpreter::from_source`.
+        // `<arr>.1` reuses the same `ExprKind::Index` node `obj.prop` does;
+        // `ArrayEngine::get` stands in for a `<path>` that resolves to
+        // array-shaped JSON.
+        let source_ast = ast::Stmt::spanned(ast::StmtKind::Print(ast::Expr::spanned(
+            ast::ExprKind::Index(
+                Box::new(ast::Expr::spanned(ast::ExprKind::Path("arr".to_string()))),
+                "1".to_string(),
+            ),
+        )));
+        let code = Interpreter::from_ast(source_ast).unwrap();
+        let engine = ArrayEngine::new();
+        let vm = VM::new(engine.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            vm.run(code, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            vec!["20".to_string()],
+            engine
+                .print_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<String>>(),
+        );
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_code_bytes_round_trip_executes() {
+        // A full compile -> serialize -> deserialize -> execute round trip
+        // (see compiler::tests::test_code_write_read_round_trip for the
+        // structural equivalent), covering `when`, `wait`, `set`, `scene`,
+        // and object indexing in one script.
+        let source = "
+            let o = {x: 1};
+            when <path> print \"off\";
+            wait 1s print \"done\";
+            set [path/to/value] \"on\";
+            scene night { print \"dark\"; };
+            start night;
+            stop night;
+            print o.x;
+    ";
+        let code = Interpreter::from_source(source).unwrap();
+        let bytes = code.to_bytes().unwrap();
+        let reloaded = Code::from_bytes(&bytes).unwrap();
+
+        let te = TestEngine::new();
+        let vm = VM::new(te.clone());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            vm.run(reloaded, shutdown_rx).await.unwrap();
+        });
+        // TODO: remove this sleep
+        time::sleep(Duration::from_millis(100)).await;
+
+        // `when`/`wait` run on independently spawned threads, so only
+        // `scene`'s "dark" (a synchronous `Call`) and the final `o.x` are
+        // guaranteed to print in program order relative to each other;
+        // sort before comparing to avoid asserting a specific interleaving
+        // with the spawned threads' output.
+        let mut prints = te
+            .print_args
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect::<Vec<String>>();
+        prints.sort();
+        assert_eq!(
+            vec![
+                "1".to_string(),
+                "dark".to_string(),
+                "done".to_string(),
+                "off".to_string(),
+            ],
+            prints,
+        );
+        assert_eq!(
+            vec![("path/to/value".to_string(), "on".to_string())],
+            te.set_args
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<(String, String)>>(),
+        );
+        let _ = shutdown_tx.send(());
     }
 }